@@ -1,8 +1,38 @@
 //! # API for the Analog to Digital converter
+//!
+//! The nRF51 has a single ADC, so several sensor drivers usually share it.
+//! `Adc` implements `OneShot` for every input, with the same `AdcTimeout`
+//! error, and so does `&mut Adc`. A driver taking a `OneShot` by value can be
+//! lent the ADC for as long as it needs it:
+//!
+//! ```ignore
+//! let mut adc = Adc::default(p.ADC);
+//! let temperature = Thermistor::new(&mut adc, pin1).read();
+//! let light = LightSensor::new(&mut adc, pin2).read();
+//! ```
+//!
+//! To share it between contexts, e.g. with an interrupt handler, put it
+//! behind a `cortex_m::interrupt::Mutex<RefCell<Adc>>` (`Adc` is `Send`) or
+//! a shared-bus style proxy that locks it around each conversion. Drivers
+//! needing a different resolution or reference should change it inside
+//! `Adc::with_channel`, which restores the configuration for the others.
+
+use core::convert::TryFrom;
 
 use embedded_hal::adc::{Channel, OneShot};
 
-use crate::gpio::{gpio, Floating, Input};
+use crate::gpio::{gpio, Floating, Input, PullDown, PullUp};
+
+#[cfg(feature = "async")]
+use core::cell::RefCell;
+#[cfg(feature = "async")]
+use core::future::Future;
+#[cfg(feature = "async")]
+use core::pin::Pin;
+#[cfg(feature = "async")]
+use core::task::{Context, Poll, Waker};
+#[cfg(feature = "async")]
+use cortex_m::interrupt::{self, Mutex};
 
 use crate::nrf51::ADC;
 
@@ -12,9 +42,138 @@ pub struct Adc {
     resolution: AdcResolution,
     input_selection: AdcInputSelection,
     reference_selection: AdcReferenceSelection,
+    /// The analog input selected in CONFIG.PSEL, if any
+    channel: Option<AdcChannel>,
+    calibration: AdcCalibration,
+}
+
+/// Offset and gain correction applied to conversion results
+#[derive(Clone, Copy)]
+struct AdcCalibration {
+    offset: i16,
+    gain_num: u16,
+    gain_den: u16,
+}
+
+/// Complete ADC configuration, saved to be restored later
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AdcConfig {
+    resolution: AdcResolution,
+    input_selection: AdcInputSelection,
+    reference_selection: AdcReferenceSelection,
+    channel: Option<AdcChannel>,
+}
+
+impl AdcConfig {
+    /// Returns the configuration a conversion of `channel` uses
+    ///
+    /// Only the analog input changes for a pin. The pseudo-channels also
+    /// select their input and the band gap reference.
+    fn sampling(self, channel: AdcChannel) -> AdcConfig {
+        let (input_selection, reference_selection) = match channel {
+            AdcChannel::Vdd => (
+                AdcInputSelection::SupplyOneThirdPrescaling,
+                AdcReferenceSelection::VBG,
+            ),
+            AdcChannel::Aref => (
+                AdcInputSelection::AnalogInputOneThirdPrescaling,
+                AdcReferenceSelection::VBG,
+            ),
+            _ => (self.input_selection, self.reference_selection),
+        };
+        AdcConfig {
+            input_selection,
+            reference_selection,
+            channel: Some(channel),
+            ..self
+        }
+    }
+
+    /// Returns the configuration left in place after a conversion of
+    /// `channel`
+    ///
+    /// A pin stays selected, while this configuration is restored after
+    /// sampling a pseudo-channel.
+    fn after(self, channel: AdcChannel) -> AdcConfig {
+        match channel {
+            AdcChannel::Vdd | AdcChannel::Aref => self,
+            _ => self.sampling(channel),
+        }
+    }
+}
+
+/// Polls of the END event after which `OneShot::read` gives up
+///
+/// A 10-bit conversion takes at most 68µs, i.e. 1088 cycles of the 16MHz CPU
+/// clock, and each poll takes at least one cycle, so this is twice the
+/// worst-case conversion time. 8- and 9-bit conversions are faster.
+const MAX_CONVERSION_POLLS: u32 = 2 * 68 * 16;
+
+/// The ADC didn't signal the end of a conversion in time
+#[derive(Debug)]
+pub struct AdcTimeout;
+
+/// An ADC input, as identified by the `Channel<ADC>` implementations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdcChannel {
+    /// Analog input 0 (P0.26)
+    AnalogInput0,
+    /// Analog input 1 (P0.27)
+    AnalogInput1,
+    /// Analog input 2 (P0.01)
+    AnalogInput2,
+    /// Analog input 3 (P0.02)
+    AnalogInput3,
+    /// Analog input 4 (P0.03)
+    AnalogInput4,
+    /// Analog input 5 (P0.04)
+    AnalogInput5,
+    /// Analog input 6 (P0.05)
+    AnalogInput6,
+    /// Analog input 7 (P0.06)
+    AnalogInput7,
+    /// The supply voltage, see `Vdd`
+    Vdd,
+    /// The external reference pin AREF1, see `Aref`
+    Aref,
+}
+
+/// The supply voltage, as a pseudo-channel which can be read with `OneShot`
+///
+/// Reading it measures VDD with 1/3 prescaling against the internal 1.2 V
+/// band gap reference, whatever input and reference selection is configured
+/// (the configuration is restored afterwards). The resolution is left as
+/// configured, so VDD = `result * 3.6 V / (2^bits - 1)`.
+pub struct Vdd;
+
+/// The external reference pin AREF1 (P0.06), as a pseudo-channel which can be
+/// read with `OneShot`
+///
+/// Reading it measures the pin with 1/3 prescaling against the internal 1.2 V
+/// band gap reference, whatever input and reference selection is configured
+/// (the configuration is restored afterwards). This allows checking the
+/// voltage of an external reference before using it. The resolution is left
+/// as configured, so AREF = `result * 3.6 V / (2^bits - 1)`.
+pub struct Aref;
+
+impl Channel<ADC> for Vdd {
+    type ID = AdcChannel;
+
+    fn channel() -> AdcChannel {
+        AdcChannel::Vdd
+    }
+}
+
+impl Channel<ADC> for Aref {
+    type ID = AdcChannel;
+
+    fn channel() -> AdcChannel {
+        AdcChannel::Aref
+    }
 }
 
 /// ADC Sample resolution
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AdcResolution {
     /// 8 bit sample resolution
     Res8bit,
@@ -32,6 +191,7 @@ impl AdcResolution {
 }
 
 /// ADC Input Selection (analog or supply prescaling)
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AdcInputSelection {
     /// Use analog input pin without prescaling
     AnalogInputNoPrescaling,
@@ -53,6 +213,7 @@ impl AdcInputSelection {
 }
 
 /// ADC reference selection
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AdcReferenceSelection {
     /// Use internal 1.2 V band gap reference
     VBG,
@@ -98,18 +259,36 @@ impl Adc {
             resolution,
             input_selection,
             reference_selection,
+            channel: None,
+            calibration: AdcCalibration {
+                offset: 0,
+                gain_num: 1,
+                gain_den: 1,
+            },
         };
 
         while s.adc.busy.read().busy().is_busy() {}
+        s.write_config();
 
-        s.adc.config.write(|w| {
-            let w1 = match s.resolution {
+        s.adc.enable.write(|w| w.enable().enabled());
+        s
+    }
+
+    /// Writes the whole CONFIG register, in a single write, from the stored
+    /// resolution, input selection, reference selection and channel
+    ///
+    /// No analog input is selected without a channel, and no external
+    /// reference input.
+    fn write_config(&self) {
+        let channel = self.channel;
+        self.adc.config.write(|w| {
+            let w1 = match self.resolution {
                 AdcResolution::Res8bit => w.res()._8bit(),
                 AdcResolution::Res9bit => w.res()._9bit(),
                 AdcResolution::Res10bit => w.res()._10bit(),
             };
 
-            let w2 = match s.input_selection {
+            let w2 = match self.input_selection {
                 AdcInputSelection::AnalogInputNoPrescaling => {
                     w1.inpsel().analog_input_no_prescaling()
                 }
@@ -127,7 +306,7 @@ impl Adc {
                 }
             };
 
-            let w3 = match s.reference_selection {
+            let w3 = match self.reference_selection {
                 AdcReferenceSelection::VBG => w2.refsel().vbg(),
                 AdcReferenceSelection::External => w2.refsel().external(),
                 AdcReferenceSelection::SupplyOneHalfPrescaling => {
@@ -138,16 +317,59 @@ impl Adc {
                 }
             };
 
-            w3
+            match channel {
+                Some(AdcChannel::AnalogInput0) => w3.psel().analog_input0(),
+                Some(AdcChannel::AnalogInput1) => w3.psel().analog_input1(),
+                Some(AdcChannel::AnalogInput2) => w3.psel().analog_input2(),
+                Some(AdcChannel::AnalogInput3) => w3.psel().analog_input3(),
+                Some(AdcChannel::AnalogInput4) => w3.psel().analog_input4(),
+                Some(AdcChannel::AnalogInput5) => w3.psel().analog_input5(),
+                Some(AdcChannel::AnalogInput6) => w3.psel().analog_input6(),
+                Some(AdcChannel::AnalogInput7) | Some(AdcChannel::Aref) => {
+                    w3.psel().analog_input7()
+                }
+                Some(AdcChannel::Vdd) | None => w3.psel().disabled(),
+            }
         });
+    }
 
-        s.adc.enable.write(|w| w.enable().enabled());
-        s
+    /// Sets the resolution, input selection, reference selection and analog
+    /// input all at once
+    ///
+    /// The individual setters (`set_resolution()` and so on) each change one
+    /// field of the CONFIG register, so in between two of them the ADC is
+    /// configured with a mix of the old and new settings. That's harmless for
+    /// conversions started by this driver, but a conversion triggered through
+    /// PPI in between would sample with the mixed configuration and give a
+    /// wrong reading. This writes the whole configuration in a single write,
+    /// so prefer it when conversions can be triggered through PPI.
+    ///
+    /// Waits for any conversion in progress to finish first. For the `Vdd`
+    /// and `Aref` pseudo-channels the matching input and reference selection
+    /// must be passed explicitly; no external reference input is selected.
+    pub fn reconfigure<PIN>(
+        &mut self,
+        resolution: AdcResolution,
+        input_selection: AdcInputSelection,
+        reference_selection: AdcReferenceSelection,
+        _pin: &mut PIN,
+    ) where
+        PIN: Channel<ADC, ID = AdcChannel>,
+    {
+        self.resolution = resolution;
+        self.input_selection = input_selection;
+        self.reference_selection = reference_selection;
+        self.channel = Some(PIN::channel());
+
+        while self.adc.busy.read().busy().is_busy() {}
+        self.write_config();
     }
 
     /// Sets the ADC resolution
     ///
     /// Options can be found in [AdcResolution](crate::adc::AdcResolution)
+    ///
+    /// See `reconfigure()` if conversions can be triggered through PPI.
     pub fn set_resolution(&mut self, resolution: AdcResolution) {
         self.resolution = resolution;
 
@@ -161,6 +383,8 @@ impl Adc {
     /// Sets the ADC input selection
     ///
     /// Options can be found in [AdcInputSelection](crate::adc::AdcInputSelection)
+    ///
+    /// See `reconfigure()` if conversions can be triggered through PPI.
     pub fn set_input_selection(&mut self, input_selection: AdcInputSelection) {
         self.input_selection = input_selection;
 
@@ -182,6 +406,8 @@ impl Adc {
     /// Sets the ADC reference selection
     ///
     /// Options can be found in [AdcReferenceSelection](crate::adc::AdcReferenceSelection)
+    ///
+    /// See `reconfigure()` if conversions can be triggered through PPI.
     pub fn set_reference_selection(&mut self, reference_selection: AdcReferenceSelection) {
         self.reference_selection = reference_selection;
 
@@ -199,29 +425,275 @@ impl Adc {
             })
     }
 
-    fn set_channel(&mut self, channel: u8) {
-        match channel {
-            0 => self.adc.config.modify(|_, w| w.psel().analog_input0()),
-            1 => self.adc.config.modify(|_, w| w.psel().analog_input1()),
-            2 => self.adc.config.modify(|_, w| w.psel().analog_input2()),
-            3 => self.adc.config.modify(|_, w| w.psel().analog_input3()),
-            4 => self.adc.config.modify(|_, w| w.psel().analog_input4()),
-            5 => self.adc.config.modify(|_, w| w.psel().analog_input5()),
-            6 => self.adc.config.modify(|_, w| w.psel().analog_input6()),
-            7 => self.adc.config.modify(|_, w| w.psel().analog_input7()),
-            _ => unreachable!(),
+    /// Runs `f` and restores the ADC configuration afterwards
+    ///
+    /// The whole configuration (resolution, input selection, reference
+    /// selection and selected analog input) is saved before calling `f` and
+    /// written back once it returns. This makes it safe to temporarily change
+    /// the configuration, e.g. to measure the supply voltage in between pin
+    /// reads, without affecting later conversions.
+    pub fn with_channel<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let saved = self.config();
+        let res = f(self);
+        self.apply_config(saved);
+        res
+    }
+
+    /// Sets the offset and gain correction applied to conversion results
+    ///
+    /// Every result is corrected as `(raw - offset) * gain_num / gain_den`,
+    /// clamped to the range of the configured resolution. This is meant for
+    /// per-device corrections measured in production. `set_calibration(0, 1,
+    /// 1)` (the default) disables the correction.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `gain_den` is 0.
+    pub fn set_calibration(&mut self, offset: i16, gain_num: u16, gain_den: u16) {
+        assert!(gain_den != 0, "calibration gain denominator is zero");
+        self.calibration = AdcCalibration {
+            offset,
+            gain_num,
+            gain_den,
+        };
+    }
+
+    fn calibrate(&self, raw: u16) -> u16 {
+        let cal = self.calibration;
+        let max = (1_i64 << self.resolution_bits()) - 1;
+        let corrected = (i64::from(raw) - i64::from(cal.offset)) * i64::from(cal.gain_num)
+            / i64::from(cal.gain_den);
+        corrected.clamp(0, max) as u16
+    }
+
+    fn config(&self) -> AdcConfig {
+        AdcConfig {
+            resolution: self.resolution,
+            input_selection: self.input_selection,
+            reference_selection: self.reference_selection,
+            channel: self.channel,
         }
     }
 
-    fn convert(&mut self, channel: u8) -> u16 {
-        self.set_channel(channel);
+    /// Stores `config` and writes it to the CONFIG register, once any
+    /// conversion in progress has finished
+    fn apply_config(&mut self, config: AdcConfig) {
+        self.resolution = config.resolution;
+        self.input_selection = config.input_selection;
+        self.reference_selection = config.reference_selection;
+        self.channel = config.channel;
+
+        while self.adc.busy.read().busy().is_busy() {}
+        self.write_config();
+    }
+
+    /// Configures the ADC to sample `channel`
+    ///
+    /// Returns the configuration to put in place once sampling is done, see
+    /// `AdcConfig::after`.
+    fn select_channel(&mut self, channel: AdcChannel) -> AdcConfig {
+        let current = self.config();
+        self.apply_config(current.sampling(channel));
+        current.after(channel)
+    }
+
+    /// Selects `channel` and runs `f` to sample it
+    fn on_channel<F, R>(&mut self, channel: AdcChannel, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let after = self.select_channel(channel);
+        let res = f(self);
+        self.apply_config(after);
+        res
+    }
+
+    /// Clears the END event and starts a conversion, enabling the ADC first
+    /// if a session has disabled it
+    fn start_conversion(&mut self) {
+        if self.adc.enable.read().enable().is_disabled() {
+            self.adc.enable.write(|w| w.enable().enabled());
+        }
         self.adc.events_end.write(|w| unsafe { w.bits(0) });
         self.adc.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
 
-        while self.adc.events_end.read().bits() == 0 {}
+    fn convert_timeout(&mut self, channel: AdcChannel, max_polls: u32) -> Result<u16, AdcTimeout> {
+        self.on_channel(channel, |adc| adc.sample_timeout(max_polls))
+    }
+
+    fn sample_timeout(&mut self, max_polls: u32) -> Result<u16, AdcTimeout> {
+        self.start_conversion();
+
+        let mut polls = 0;
+        while self.adc.events_end.read().bits() == 0 {
+            if polls == max_polls {
+                // Abort the conversion so the next one starts from a clean state
+                self.adc.tasks_stop.write(|w| unsafe { w.bits(1) });
+                return Err(AdcTimeout);
+            }
+            polls += 1;
+        }
 
         self.adc.events_end.write(|w| unsafe { w.bits(0) });
-        self.adc.result.read().result().bits()
+        Ok(self.calibrate(self.adc.result.read().result().bits()))
+    }
+
+    /// Performs a blocking conversion on `pin`, giving up after `max_polls`
+    /// checks of the END event
+    ///
+    /// Like the `OneShot` implementation, but with a caller-chosen bound
+    /// instead of one derived from the worst-case conversion time. Returns an
+    /// error if the ADC doesn't finish the conversion in time (for example
+    /// because it is misconfigured). The running conversion is stopped before
+    /// returning the error, so it is safe to retry.
+    pub fn read_blocking_timeout<PIN>(
+        &mut self,
+        _pin: &mut PIN,
+        max_polls: u32,
+    ) -> Result<u16, AdcTimeout>
+    where
+        PIN: Channel<ADC, ID = AdcChannel>,
+    {
+        self.convert_timeout(PIN::channel(), max_polls)
+    }
+
+    /// Returns the configured sample resolution in bits (8, 9 or 10)
+    ///
+    /// Conversion results are right-aligned, i.e. in the range
+    /// `0 ..= 2^bits - 1`.
+    pub fn resolution_bits(&self) -> u8 {
+        match self.resolution {
+            AdcResolution::Res8bit => 8,
+            AdcResolution::Res9bit => 9,
+            AdcResolution::Res10bit => 10,
+        }
+    }
+
+    /// Converts a raw conversion result to the measured voltage in millivolts
+    ///
+    /// Takes the configured resolution, input prescaling and reference into
+    /// account. `supply_mv` is the voltage of VDD when one of the supply
+    /// based references is selected, or of the AREF pin when the external
+    /// reference is selected; it is ignored for the band gap reference.
+    ///
+    /// When the input is one of the supply inputs the result is VDD. A result
+    /// beyond `u16::MAX`, e.g. from a `raw` beyond the resolution or an
+    /// unrealistically high `supply_mv`, saturates.
+    pub fn to_millivolts(&self, raw: u16, supply_mv: u16) -> u16 {
+        let (ref_num, ref_den) = match self.reference_selection {
+            AdcReferenceSelection::VBG => (1200, 1),
+            AdcReferenceSelection::External => (u32::from(supply_mv), 1),
+            AdcReferenceSelection::SupplyOneHalfPrescaling => (u32::from(supply_mv), 2),
+            AdcReferenceSelection::SupplyOneThirdPrescaling => (u32::from(supply_mv), 3),
+        };
+        let (pre_num, pre_den) = match self.input_selection {
+            AdcInputSelection::AnalogInputNoPrescaling => (1, 1),
+            AdcInputSelection::AnalogInputTwoThirdsPrescaling
+            | AdcInputSelection::SupplyTwoThirdsPrescaling => (3, 2),
+            AdcInputSelection::AnalogInputOneThirdPrescaling
+            | AdcInputSelection::SupplyOneThirdPrescaling => (3, 1),
+        };
+        let max = (1 << self.resolution_bits()) - 1;
+        let millivolts = u32::from(raw) * ref_num * pre_num / (max * ref_den * pre_den);
+        u16::try_from(millivolts).unwrap_or(u16::MAX)
+    }
+
+    /// Performs a blocking conversion on `pin`, returning both the result
+    /// and the voltage it corresponds to in millivolts
+    ///
+    /// Both come from the same conversion, and the voltage is computed with
+    /// the configuration used for it, which for the `Vdd` and `Aref`
+    /// pseudo-channels differs from the configuration in place before and
+    /// after. The result is corrected by `set_calibration()` as for
+    /// `OneShot::read`; `supply_mv` is as for `to_millivolts()`.
+    pub fn read_with_millivolts<PIN>(
+        &mut self,
+        _pin: &mut PIN,
+        supply_mv: u16,
+    ) -> Result<(u16, u16), AdcTimeout>
+    where
+        PIN: Channel<ADC, ID = AdcChannel>,
+    {
+        self.on_channel(PIN::channel(), |adc| {
+            let raw = adc.sample_timeout(MAX_CONVERSION_POLLS)?;
+            Ok((raw, adc.to_millivolts(raw, supply_mv)))
+        })
+    }
+
+    /// Measures the supply voltage in millivolts
+    ///
+    /// This is the accurate way to measure VDD (e.g. as a battery gauge): it
+    /// uses the internal 1.2 V band gap reference, which doesn't depend on the
+    /// supply, and the supply input with 1/3 prescaling, at 10 bit resolution.
+    /// The result follows from the datasheet's conversion formula:
+    ///
+    /// ```text
+    /// result = VDD * 1/3 * 1023 / 1.2 V
+    ///    VDD = result / 1023 * 1.2 V * 3
+    /// ```
+    ///
+    /// so VDD in millivolts is `result * 3600 / 1023`, which is what is
+    /// returned (rounded to the nearest millivolt). The full scale of 3.6 V
+    /// covers the supply range of the nRF51.
+    ///
+    /// The configuration is changed for the measurement only and restored
+    /// afterwards. The calibration set with `set_calibration()` is applied to
+    /// the raw result.
+    ///
+    /// Returns `AdcTimeout` if the conversion doesn't finish in time, as for
+    /// `OneShot::read`.
+    pub fn read_vdd_precise_millivolts(&mut self) -> Result<u16, AdcTimeout> {
+        self.with_channel(|adc| {
+            adc.set_resolution(AdcResolution::Res10bit);
+            adc.set_input_selection(AdcInputSelection::SupplyOneThirdPrescaling);
+            adc.set_reference_selection(AdcReferenceSelection::VBG);
+            let result = u32::from(adc.sample_timeout(MAX_CONVERSION_POLLS)?);
+            Ok(((result * 3600 + 1023 / 2) / 1023) as u16)
+        })
+    }
+
+    /// Starts a conversion on `pin`, returning a future resolving to the result
+    ///
+    /// The conversion is started when the future is first polled. While it is
+    /// running the ADC's END interrupt is enabled; its handler must call
+    /// `on_end_interrupt()` to wake the task awaiting the future:
+    ///
+    /// ```ignore
+    /// #[interrupt]
+    /// fn ADC() {
+    ///     nrf51_hal::adc::on_end_interrupt();
+    /// }
+    /// ```
+    ///
+    /// and the interrupt must be unmasked in the NVIC. Dropping the future
+    /// before it resolves stops the conversion.
+    ///
+    /// Only available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn read_async<PIN>(&mut self, _pin: &mut PIN) -> ReadFuture<'_>
+    where
+        PIN: Channel<ADC, ID = AdcChannel>,
+    {
+        ReadFuture {
+            adc: self,
+            channel: PIN::channel(),
+            state: ReadState::Idle,
+        }
+    }
+
+    /// Starts a session for a batch of conversions
+    ///
+    /// The ADC is enabled for the lifetime of the returned guard, and
+    /// disabled when it is dropped, once any running conversion has finished.
+    /// This guarantees the ADC is powered down after a burst of reads. Reading
+    /// with the `Adc` itself afterwards enables it again.
+    pub fn session(&mut self) -> AdcSession<'_> {
+        self.adc.enable.write(|w| w.enable().enabled());
+        AdcSession { adc: self }
     }
 
     /// Disables the ADC and releases the ADC peripheral
@@ -234,35 +706,272 @@ impl Adc {
 impl<WORD, PIN> OneShot<ADC, WORD, PIN> for Adc
 where
     WORD: From<u16>,
-    PIN: Channel<ADC, ID = u8>,
+    PIN: Channel<ADC, ID = AdcChannel>,
 {
-    type Error = ();
+    type Error = AdcTimeout;
 
+    /// Performs a blocking conversion on `pin`
+    ///
+    /// `pin` can be a floating input or one with its pull-up or pull-down
+    /// resistor enabled. The pull resistor is connected to the analog input
+    /// during the conversion, so with a pulled input the result depends on
+    /// the impedance of whatever drives the pin.
+    ///
+    /// The result is right-aligned at the configured resolution, see
+    /// `resolution_bits()`. Use `to_millivolts()` to convert it to a voltage.
+    ///
+    /// Returns `AdcTimeout` if the conversion doesn't finish within twice
+    /// the worst-case conversion time (for example because the ADC is
+    /// misconfigured), instead of waiting forever.
     fn read(&mut self, _pin: &mut PIN) -> nb::Result<WORD, Self::Error> {
-        let res = self.convert(PIN::channel());
+        let res = self.convert_timeout(PIN::channel(), MAX_CONVERSION_POLLS)?;
         Ok(res.into())
     }
 }
 
+/// Lends the `Adc` to a driver taking a `OneShot` by value
+///
+/// Conversions behave exactly as with the `Adc` itself.
+impl<WORD, PIN> OneShot<ADC, WORD, PIN> for &mut Adc
+where
+    WORD: From<u16>,
+    PIN: Channel<ADC, ID = AdcChannel>,
+{
+    type Error = AdcTimeout;
+
+    fn read(&mut self, pin: &mut PIN) -> nb::Result<WORD, Self::Error> {
+        OneShot::<ADC, WORD, PIN>::read(&mut **self, pin)
+    }
+}
+
+// The Adc is meant to be shared, including with interrupt handlers
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Adc>();
+};
+
+/// A batch of conversions with the ADC enabled, started by `Adc::session`
+pub struct AdcSession<'a> {
+    adc: &'a mut Adc,
+}
+
+impl<'a> AdcSession<'a> {
+    /// Performs a blocking conversion on `pin`, like the `OneShot`
+    /// implementation of `Adc`
+    pub fn read<PIN>(&mut self, _pin: &mut PIN) -> Result<u16, AdcTimeout>
+    where
+        PIN: Channel<ADC, ID = AdcChannel>,
+    {
+        self.adc
+            .convert_timeout(PIN::channel(), MAX_CONVERSION_POLLS)
+    }
+
+    /// Performs a blocking conversion on `pin`, giving up after `max_polls`
+    /// checks of the END event, see `Adc::read_blocking_timeout`
+    pub fn read_blocking_timeout<PIN>(
+        &mut self,
+        pin: &mut PIN,
+        max_polls: u32,
+    ) -> Result<u16, AdcTimeout>
+    where
+        PIN: Channel<ADC, ID = AdcChannel>,
+    {
+        self.adc.read_blocking_timeout(pin, max_polls)
+    }
+}
+
+impl<'a> Drop for AdcSession<'a> {
+    fn drop(&mut self) {
+        // Disabling the ADC in the middle of a conversion is undefined
+        while self.adc.adc.busy.read().busy().is_busy() {}
+        self.adc.adc.enable.write(|w| w.enable().disabled());
+    }
+}
+
+/// Task waiting for the END event, woken by `on_end_interrupt`
+#[cfg(feature = "async")]
+static END_WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+
+/// Handles the ADC interrupt for `Adc::read_async`
+///
+/// Call this from the ADC interrupt handler. It disables the END interrupt
+/// and wakes the task waiting for the running conversion, which then picks up
+/// the result.
+///
+/// Only available with the `async` feature.
+#[cfg(feature = "async")]
+pub fn on_end_interrupt() {
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe { (*ADC::ptr()).intenclr.write(|w| w.end().clear()) };
+    interrupt::free(|cs| {
+        if let Some(waker) = END_WAKER.borrow(cs).borrow_mut().take() {
+            waker.wake();
+        }
+    });
+}
+
+#[cfg(feature = "async")]
+enum ReadState {
+    Idle,
+    /* Holds the configuration to put in place once sampling is done */
+    Running(AdcConfig),
+    Done,
+}
+
+/// A conversion started by `Adc::read_async`
+///
+/// Only available with the `async` feature.
+#[cfg(feature = "async")]
+pub struct ReadFuture<'a> {
+    adc: &'a mut Adc,
+    channel: AdcChannel,
+    state: ReadState,
+}
+
+#[cfg(feature = "async")]
+impl<'a> ReadFuture<'a> {
+    fn finish(&mut self) {
+        if let ReadState::Running(after) = core::mem::replace(&mut self.state, ReadState::Done) {
+            self.adc.adc.intenclr.write(|w| w.end().clear());
+            self.adc.adc.events_end.write(|w| unsafe { w.bits(0) });
+            self.adc.apply_config(after);
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a> Future for ReadFuture<'a> {
+    type Output = u16;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u16> {
+        let this = &mut *self;
+
+        if let ReadState::Idle = this.state {
+            let after = this.adc.select_channel(this.channel);
+            this.adc.start_conversion();
+            this.state = ReadState::Running(after);
+        }
+
+        // Register before checking the event, so an END arriving in between
+        // still wakes the task
+        interrupt::free(|cs| {
+            END_WAKER.borrow(cs).replace(Some(cx.waker().clone()));
+        });
+
+        if this.adc.adc.events_end.read().bits() != 0 {
+            let raw = this.adc.adc.result.read().result().bits();
+            this.finish();
+            Poll::Ready(this.adc.calibrate(raw))
+        } else {
+            this.adc.adc.intenset.write(|w| w.end().set());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a> Drop for ReadFuture<'a> {
+    fn drop(&mut self) {
+        if let ReadState::Running(_) = self.state {
+            self.adc.adc.tasks_stop.write(|w| unsafe { w.bits(1) });
+            self.finish();
+        }
+    }
+}
+
 macro_rules! adc_pins {
     ($($pin:ty => $chan:expr),+ $(,)*) => {
         $(
             impl Channel<ADC> for $pin {
-                type ID = u8;
+                type ID = AdcChannel;
 
-                fn channel() -> u8 { $chan }
+                fn channel() -> AdcChannel { $chan }
             }
         )+
     };
 }
 
 adc_pins!(
-    gpio::PIN26<Input<Floating>> => 0_u8,
-    gpio::PIN27<Input<Floating>> => 1_u8,
-    gpio::PIN1<Input<Floating>> => 2u8,
-    gpio::PIN2<Input<Floating>> => 3u8,
-    gpio::PIN3<Input<Floating>> => 4u8,
-    gpio::PIN4<Input<Floating>> => 5u8,
-    gpio::PIN5<Input<Floating>> => 6u8,
-    gpio::PIN6<Input<Floating>> => 7u8,
+    gpio::PIN26<Input<Floating>> => AdcChannel::AnalogInput0,
+    gpio::PIN27<Input<Floating>> => AdcChannel::AnalogInput1,
+    gpio::PIN1<Input<Floating>> => AdcChannel::AnalogInput2,
+    gpio::PIN2<Input<Floating>> => AdcChannel::AnalogInput3,
+    gpio::PIN3<Input<Floating>> => AdcChannel::AnalogInput4,
+    gpio::PIN4<Input<Floating>> => AdcChannel::AnalogInput5,
+    gpio::PIN5<Input<Floating>> => AdcChannel::AnalogInput6,
+    gpio::PIN6<Input<Floating>> => AdcChannel::AnalogInput7,
+    // Sampling a pulled input, e.g. to bias a resistive divider. The pull
+    // resistor (13kΩ typical) stays connected during the conversion and
+    // loads the signal, so the reading depends on the source impedance.
+    gpio::PIN26<Input<PullUp>> => AdcChannel::AnalogInput0,
+    gpio::PIN27<Input<PullUp>> => AdcChannel::AnalogInput1,
+    gpio::PIN1<Input<PullUp>> => AdcChannel::AnalogInput2,
+    gpio::PIN2<Input<PullUp>> => AdcChannel::AnalogInput3,
+    gpio::PIN3<Input<PullUp>> => AdcChannel::AnalogInput4,
+    gpio::PIN4<Input<PullUp>> => AdcChannel::AnalogInput5,
+    gpio::PIN5<Input<PullUp>> => AdcChannel::AnalogInput6,
+    gpio::PIN6<Input<PullUp>> => AdcChannel::AnalogInput7,
+    gpio::PIN26<Input<PullDown>> => AdcChannel::AnalogInput0,
+    gpio::PIN27<Input<PullDown>> => AdcChannel::AnalogInput1,
+    gpio::PIN1<Input<PullDown>> => AdcChannel::AnalogInput2,
+    gpio::PIN2<Input<PullDown>> => AdcChannel::AnalogInput3,
+    gpio::PIN3<Input<PullDown>> => AdcChannel::AnalogInput4,
+    gpio::PIN4<Input<PullDown>> => AdcChannel::AnalogInput5,
+    gpio::PIN5<Input<PullDown>> => AdcChannel::AnalogInput6,
+    gpio::PIN6<Input<PullDown>> => AdcChannel::AnalogInput7,
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudo_channel_reads_restore_the_pin_configuration() {
+        let configured = AdcConfig {
+            resolution: AdcResolution::Res9bit,
+            input_selection: AdcInputSelection::AnalogInputTwoThirdsPrescaling,
+            reference_selection: AdcReferenceSelection::SupplyOneHalfPrescaling,
+            channel: None,
+        };
+        let pin = |channel| AdcConfig {
+            channel: Some(channel),
+            ..configured
+        };
+
+        // Pin reads interleaved with VDD and AREF reads
+        let reads = [
+            (AdcChannel::AnalogInput2, pin(AdcChannel::AnalogInput2)),
+            (AdcChannel::Vdd, pin(AdcChannel::AnalogInput2)),
+            (AdcChannel::AnalogInput5, pin(AdcChannel::AnalogInput5)),
+            (AdcChannel::Vdd, pin(AdcChannel::AnalogInput5)),
+            (AdcChannel::Aref, pin(AdcChannel::AnalogInput5)),
+            (AdcChannel::AnalogInput2, pin(AdcChannel::AnalogInput2)),
+        ];
+        let mut config = configured;
+        for (channel, expected_after) in reads.iter() {
+            let sampling = config.sampling(*channel);
+            assert_eq!(sampling.resolution, AdcResolution::Res9bit);
+            assert_eq!(sampling.channel, Some(*channel));
+            let (input_selection, reference_selection) = match channel {
+                AdcChannel::Vdd => (
+                    AdcInputSelection::SupplyOneThirdPrescaling,
+                    AdcReferenceSelection::VBG,
+                ),
+                AdcChannel::Aref => (
+                    AdcInputSelection::AnalogInputOneThirdPrescaling,
+                    AdcReferenceSelection::VBG,
+                ),
+                _ => (configured.input_selection, configured.reference_selection),
+            };
+            assert_eq!(sampling.input_selection, input_selection, "{:?}", channel);
+            assert_eq!(
+                sampling.reference_selection, reference_selection,
+                "{:?}",
+                channel
+            );
+
+            config = config.after(*channel);
+            assert_eq!(config, *expected_after, "after {:?}", channel);
+        }
+    }
+}
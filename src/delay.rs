@@ -6,7 +6,7 @@ use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 
 use crate::hi_res_timer::{HiResTimer, Nrf51Timer, TimerCc, TimerFrequency, TimerWidth};
 use crate::lo_res_timer::{LoResTimer, Nrf51Rtc, RtcCc, RtcFrequency};
-use crate::time::{Hfticks, Lfticks};
+use crate::time::{ClockTicks, Hfticks, Lfticks};
 
 /// A TIMER peripheral as a delay provider.
 ///
@@ -55,10 +55,8 @@ impl<T: Nrf51Timer> DelayTimer<T> {
     }
 
     fn delay(&mut self, hfticks: Hfticks) {
-        let ticks = self
-            .timer
-            .frequency()
-            .scale(hfticks.0)
+        let ticks = hfticks
+            .scale(self.timer.frequency())
             .expect("TIMER compare value overflow");
         let ticks = T::MaxWidth::try_from_u32(ticks).expect("TIMER compare value too wide");
         self.timer.clear();
@@ -104,6 +102,140 @@ impl<T: Nrf51Timer> DelayUs<u8> for DelayTimer<T> {
     }
 }
 
+/// A delay provider borrowing a TIMER that is also used for something else.
+///
+/// `SharedDelayTimer` uses a single CC register (CC1, CC2 or CC3) of an
+/// already configured, running `HiResTimer`, leaving the counter itself
+/// alone. This lets one physical TIMER provide delays while also serving as,
+/// for instance, a `CountDownTimer` (which needs CC0).
+///
+/// Channel allocation: CC0 is reserved for `CountDownTimer` and `DelayTimer`,
+/// so it can't be used here. Make sure nothing else uses the chosen CC
+/// register while a `SharedDelayTimer` borrows the timer.
+///
+/// The TIMER must be running, otherwise the delay never ends. If CC0 is set
+/// to clear the counter (as `CountDownTimer` does), the counter wraps every
+/// CC0 + 1 ticks rather than at its bit-width, and delays must be shorter
+/// than that period. With CC0 set to 0 the counter never moves, so only zero
+/// delays are possible.
+///
+/// The counter is read by capturing it into the chosen CC register, polling
+/// until the requested number of ticks has passed. Unlike waiting for a
+/// COMPARE event, this can't miss a target the counter reaches while the
+/// delay is being set up, which would mean waiting a whole wrapping period.
+/// A wrapping period shorter than one poll (only possible with a tiny CC0
+/// value) can't be measured reliably.
+///
+/// `SharedDelayTimer` instances implement the embedded-hal `DelayMs` and
+/// `DelayUs` traits (for `u8`, `u16`, and `u32`).
+///
+/// # Panics
+///
+/// `new()` panics if `register` is CC0.
+///
+/// `delay_ms()` and `delay_us()` panic if the requested time requires as many
+/// ticks as the counter's wrapping period, or more.
+///
+/// # Example
+/// ```ignore
+/// use embedded_hal::blocking::delay::DelayMs;
+/// use embedded_hal::timer::CountDown;
+/// use nrf51_hal::delay::SharedDelayTimer;
+/// use nrf51_hal::hi_res_timer::{TimerCc, TimerFrequency};
+/// use nrf51_hal::timer::CountDownTimer;
+/// let p = nrf51::Peripherals::take().unwrap();
+/// let mut tick = CountDownTimer::new(p.TIMER0, TimerFrequency::Freq1MHz);
+/// tick.start(Duration::from_millis(100));
+/// SharedDelayTimer::new(tick.hi_res_timer(), TimerCc::CC1).delay_ms(5_u32);
+/// ```
+pub struct SharedDelayTimer<'a, T: Nrf51Timer, Width: TimerWidth> {
+    timer: &'a mut HiResTimer<T, Width>,
+    register: TimerCc,
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> SharedDelayTimer<'a, T, Width> {
+    /// Returns a new `SharedDelayTimer` borrowing the passed `HiResTimer`
+    /// and using the specified CC register.
+    pub fn new(timer: &'a mut HiResTimer<T, Width>, register: TimerCc) -> Self {
+        if let TimerCc::CC0 = register {
+            panic!("CC0 can't be used for a shared delay");
+        }
+        SharedDelayTimer { timer, register }
+    }
+
+    fn now(&mut self) -> u64 {
+        self.timer.capture(self.register);
+        u64::from(self.timer.captured_counter(self.register))
+    }
+
+    fn delay(&mut self, hfticks: Hfticks) {
+        let ticks = u64::from(
+            hfticks
+                .scale(self.timer.frequency())
+                .expect("TIMER compare value overflow"),
+        );
+        if ticks == 0 {
+            return;
+        }
+        // With auto-clear the counter runs from 0 up to CC0 inclusive
+        let period = if self.timer.is_auto_clear_enabled(TimerCc::CC0) {
+            u64::from(self.timer.captured_counter(TimerCc::CC0)) + 1
+        } else {
+            u64::from(Width::max_value()) + 1
+        };
+        assert!(ticks < period, "TIMER compare value too wide");
+
+        // Polling the counter, rather than waiting for a COMPARE event, means
+        // a target the counter passed while it was being set up isn't missed
+        let start = self.now();
+        let mut elapsed = 0;
+        loop {
+            let now = (self.now() + period - start) % period;
+            // Going backwards means the counter wrapped, passing the target
+            if now >= ticks || now < elapsed {
+                break;
+            }
+            elapsed = now;
+        }
+    }
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> DelayMs<u32> for SharedDelayTimer<'a, T, Width> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay(Hfticks::from_ms(ms));
+    }
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> DelayMs<u16> for SharedDelayTimer<'a, T, Width> {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(u32::from(ms));
+    }
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> DelayMs<u8> for SharedDelayTimer<'a, T, Width> {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(u32::from(ms));
+    }
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> DelayUs<u32> for SharedDelayTimer<'a, T, Width> {
+    fn delay_us(&mut self, us: u32) {
+        self.delay(Hfticks::from_us(us));
+    }
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> DelayUs<u16> for SharedDelayTimer<'a, T, Width> {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(u32::from(us))
+    }
+}
+
+impl<'a, T: Nrf51Timer, Width: TimerWidth> DelayUs<u8> for SharedDelayTimer<'a, T, Width> {
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(u32::from(us))
+    }
+}
+
 /// An RTC peripheral as a delay provider.
 ///
 /// `DelayRTC` instances implement the embedded-hal `DelayMs` and `DelayUs`
@@ -152,10 +284,8 @@ impl<T: Nrf51Rtc> DelayRtc<T> {
     }
 
     fn delay(&mut self, lfticks: Lfticks) {
-        let ticks = self
-            .timer
-            .frequency()
-            .scale(lfticks.0)
+        let ticks = lfticks
+            .scale(self.timer.frequency())
             .expect("RTC compare value overflow");
         self.timer.clear();
         self.timer.set_compare_register(RtcCc::CC0, ticks);
@@ -269,3 +399,13 @@ impl DelayUs<u8> for Delay {
         self.0.delay_us(us)
     }
 }
+
+/// Busy-waits for at least `cycles` cycles of the 16MHz core clock.
+///
+/// `cortex_m::asm::delay` counts loop iterations rather than cycles; on the
+/// Cortex-M0 an iteration (`subs` plus a taken `bne`) takes 4 cycles, so it's
+/// passed a quarter of `cycles`. Interrupts serviced meanwhile lengthen the
+/// wait.
+pub(crate) fn busy_wait_cycles(cycles: u32) {
+    cortex_m::asm::delay(cycles / 4)
+}
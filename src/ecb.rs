@@ -1,52 +1,105 @@
 //! The AES-ECB peripheral.
 
+use core::sync::atomic::{compiler_fence, Ordering};
+
 use nrf51::ECB;
 
 /// A safe, blocking wrapper around the AES-ECB peripheral.
 ///
 /// It's really just blockwise AES and not an ECB stream cipher. Blocks can be
-/// en- and decrypted by calling `crypt_block`.
+/// encrypted by calling `crypt_block` or `encrypt_block`, or without blocking
+/// by calling `start_crypt` and `poll_crypt`.
+///
+/// The hardware only implements the forward AES-128 transform, so blocks can't
+/// be decrypted. Modes which only use the forward transform, like CTR, CCM or
+/// CMAC, can be built on top of it.
+///
+/// With the `cipher` feature, `EcbCipher` makes it usable through the
+/// `cipher` crate's traits.
 pub struct AesEcb {
     regs: ECB,
+    buffer: Option<&'static mut EcbBuffer>,
+    busy: bool,
+}
+
+/// The memory area the AES-ECB peripheral reads its input from and writes its
+/// output to.
+///
+/// For the non-blocking operations this must outlive the operation, so it is
+/// handed over as a `&'static mut`, e.g. from a `static mut` or
+/// `cortex_m::singleton!`:
+///
+/// ```ignore
+/// static mut BUFFER: EcbBuffer = EcbBuffer::new();
+/// let buffer = unsafe { &mut BUFFER };
+/// ```
+#[repr(C)]
+pub struct EcbBuffer {
+    key: [u8; 16],
+    cleartext: [u8; 16],
+    ciphertext: [u8; 16],
+    // Cleartext and Ciphertext are a lie - `cleartext` is always the
+    // input, while `ciphertext` is always the output.
+}
+
+impl EcbBuffer {
+    /// Returns a zeroed buffer.
+    pub const fn new() -> Self {
+        EcbBuffer {
+            key: [0; 16],
+            cleartext: [0; 16],
+            ciphertext: [0; 16],
+        }
+    }
+}
+
+impl Default for EcbBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AesEcb {
     /// Takes ownership of the `ECB` peripheral, returning a safe wrapper.
     pub fn new(regs: ECB) -> Self {
-        Self { regs }
+        Self {
+            regs,
+            buffer: None,
+            busy: false,
+        }
     }
 
     /// Destroys `self`, giving the `ECB` peripheral back.
+    ///
+    /// An operation started with `start_crypt` is stopped.
     pub fn into_inner(self) -> ECB {
+        if self.busy {
+            self.regs.tasks_stopecb.write(|w| unsafe { w.bits(1) });
+        }
         self.regs
     }
 
-    /// Blocking encryption and decryption.
+    /// Blocking encryption.
     ///
-    /// Encrypts or decrypts `block` with `key` (encryption and decryption are
-    /// the same operation).
+    /// Encrypts `block` with `key`. Note that this is always the forward AES
+    /// transform: encrypting a ciphertext doesn't decrypt it.
     ///
     /// # Errors
     ///
     /// An error will be returned when the AES hardware raises an `ERRORECB`
     /// event. This can happen when an operation is started that shares the AES
-    /// hardware resources with the AES ECB peripheral while an en-/decryption
+    /// hardware resources with the AES ECB peripheral while an encryption
     /// operation is running.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an operation started with `start_crypt` is still running.
     pub fn crypt_block(&mut self, block: [u8; 16], key: [u8; 16]) -> Result<[u8; 16], AesEcbError> {
-        // Assumption: No operation is running when this is called.
-
-        #[repr(C)]
-        struct EcbData {
-            key: [u8; 16],
-            cleartext: [u8; 16],
-            ciphertext: [u8; 16],
-            // Cleartext and Ciphertext are a lie - `cleartext` is always the
-            // input, while `ciphertext` is always the output.
-        }
+        assert!(!self.busy, "an ECB operation is already running");
 
         // We allocate the DMA'd buffer on the stack, which means that we must
         // not panic or return before the AES operation is finished.
-        let mut buf = EcbData {
+        let mut buf = EcbBuffer {
             key,
             cleartext: block,
             ciphertext: [0; 16],
@@ -73,8 +126,250 @@ impl AesEcb {
             }
         }
     }
+
+    /// Blocking in-place encryption.
+    ///
+    /// Encrypts `block` with `key`, replacing its contents with the
+    /// ciphertext. This is the shape of the `encrypt_block` operation of
+    /// block cipher APIs; see `crypt_block` for the errors and panics.
+    ///
+    /// `block` is left unchanged if an error is returned.
+    pub fn encrypt_block(
+        &mut self,
+        key: [u8; 16],
+        block: &mut [u8; 16],
+    ) -> Result<(), AesEcbError> {
+        *block = self.crypt_block(*block, key)?;
+        Ok(())
+    }
+
+    /// Starts a non-blocking encryption.
+    ///
+    /// Encrypts `block` with `key`, using `buffer` as the DMA
+    /// buffer. The hardware accesses `buffer` until the operation is
+    /// finished, which is why it must be `'static`; `self` keeps it until
+    /// then. Call `poll_crypt` to get the result, and `take_buffer` to get the
+    /// buffer back afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an operation is already running.
+    pub fn start_crypt(&mut self, buffer: &'static mut EcbBuffer, block: [u8; 16], key: [u8; 16]) {
+        assert!(!self.busy, "an ECB operation is already running");
+
+        buffer.key = key;
+        buffer.cleartext = block;
+        let ptr = &mut *buffer as *mut EcbBuffer as u32;
+        self.buffer = Some(buffer);
+        self.busy = true;
+
+        // Make sure the buffer is written before the hardware reads it
+        compiler_fence(Ordering::SeqCst);
+
+        unsafe {
+            self.regs.events_endecb.reset(); // acknowledge left-over events
+            self.regs.events_errorecb.reset();
+            self.regs.ecbdataptr.write(|w| w.bits(ptr));
+            self.regs.tasks_startecb.write(|w| w.bits(1));
+        }
+    }
+
+    /// Checks whether the operation started with `start_crypt` is finished.
+    ///
+    /// Returns the encrypted block once it is, and clears the event.
+    /// This can be called from the ECB interrupt handler (see
+    /// `enable_interrupts`).
+    ///
+    /// # Errors
+    ///
+    /// Returns `WouldBlock` while the operation is running, and `AesEcbError`
+    /// under the same conditions as `crypt_block`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no operation was started.
+    pub fn poll_crypt(&mut self) -> nb::Result<[u8; 16], AesEcbError> {
+        assert!(self.busy, "no ECB operation was started");
+
+        if self.regs.events_endecb.read().bits() != 0 {
+            self.regs.events_endecb.reset();
+            self.busy = false;
+            // The hardware is done with the buffer
+            compiler_fence(Ordering::SeqCst);
+            let buffer = self.buffer.as_ref().expect("ECB buffer missing");
+            return Ok(buffer.ciphertext);
+        }
+
+        if self.regs.events_errorecb.read().bits() != 0 {
+            self.regs.events_errorecb.reset();
+            self.busy = false;
+            return Err(nb::Error::Other(AesEcbError));
+        }
+
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Gives back the buffer passed to `start_crypt`.
+    ///
+    /// Returns `None` while the operation is still running (or if there is no
+    /// buffer).
+    pub fn take_buffer(&mut self) -> Option<&'static mut EcbBuffer> {
+        if self.busy {
+            None
+        } else {
+            self.buffer.take()
+        }
+    }
+
+    /// Enables the ECB interrupt for the ENDECB and ERRORECB events.
+    ///
+    /// The interrupt handler should call `poll_crypt`, which clears the
+    /// events.
+    pub fn enable_interrupts(&mut self) {
+        self.regs
+            .intenset
+            .write(|w| w.endecb().set().errorecb().set());
+    }
+
+    /// Disables the ECB interrupt for the ENDECB and ERRORECB events.
+    pub fn disable_interrupts(&mut self) {
+        self.regs
+            .intenclr
+            .write(|w| w.endecb().clear().errorecb().clear());
+    }
 }
 
-/// An `ERRORECB` event was raised during an encryption or decryption operation.
+/// An `ERRORECB` event was raised during an encryption operation.
 #[derive(Debug)]
 pub struct AesEcbError;
+
+#[cfg(feature = "cipher")]
+pub use self::block_cipher::EcbCipher;
+
+/// `cipher` crate trait implementations, see `EcbCipher`
+#[cfg(feature = "cipher")]
+mod block_cipher {
+    use core::cell::RefCell;
+
+    use cipher::consts::{U1, U16};
+    use cipher::inout::InOut;
+    use cipher::{
+        Block, BlockBackend, BlockCipher, BlockClosure, BlockEncrypt, BlockSizeUser, Key, KeyInit,
+        KeySizeUser, ParBlocksSizeUser,
+    };
+    use cortex_m::interrupt::{self, Mutex};
+
+    use super::AesEcb;
+
+    /// The `AesEcb` shared by every `EcbCipher`, see `EcbCipher::install()`
+    static INSTALLED: Mutex<RefCell<Option<AesEcb>>> = Mutex::new(RefCell::new(None));
+
+    /// How often a block aborted by an `ERRORECB` event is retried
+    ///
+    /// The event means another peripheral, like the CCM or AAR used by the
+    /// radio, took over the AES hardware, so a later attempt can succeed.
+    const MAX_RETRIES: u8 = 8;
+
+    /// AES-128 with a fixed key, for use through the `cipher` crate's `KeyInit`
+    /// and `BlockEncrypt` traits.
+    ///
+    /// `KeyInit::new()` only gets the key, so the peripheral can't be passed to
+    /// it: hand the `AesEcb` over once with `EcbCipher::install()`, after which
+    /// any number of `EcbCipher`s (with different keys) share it. The blocks
+    /// passed to a `BlockEncrypt` method are encrypted one at a time with
+    /// `AesEcb::crypt_block`, each inside its own critical section, so
+    /// interrupts are only held off while a single block is encrypted.
+    ///
+    /// There is no `BlockDecrypt` implementation: the hardware only does the
+    /// forward AES transform, and running it on a ciphertext doesn't decrypt it.
+    /// Constructions which only use the forward transform, like CTR, CMAC or
+    /// CCM, work on top of `BlockEncrypt` alone.
+    ///
+    /// Only available with the `cipher` feature.
+    ///
+    /// # Panics
+    ///
+    /// Encrypting panics if no `AesEcb` is installed, or under the same
+    /// conditions as `AesEcb::crypt_block`. A block aborted by an `ERRORECB`
+    /// event is retried up to 8 times; as `BlockEncrypt` has no way
+    /// to report an error, running out of retries panics too.
+    #[derive(Clone)]
+    pub struct EcbCipher {
+        key: [u8; 16],
+    }
+
+    impl EcbCipher {
+        /// Hands `ecb` over for use by all `EcbCipher`s.
+        ///
+        /// Returns the `AesEcb` installed before, if any.
+        pub fn install(ecb: AesEcb) -> Option<AesEcb> {
+            interrupt::free(|cs| INSTALLED.borrow(cs).replace(Some(ecb)))
+        }
+
+        /// Takes the installed `AesEcb` back, if any.
+        pub fn uninstall() -> Option<AesEcb> {
+            interrupt::free(|cs| INSTALLED.borrow(cs).replace(None))
+        }
+    }
+
+    impl KeySizeUser for EcbCipher {
+        type KeySize = U16;
+    }
+
+    impl KeyInit for EcbCipher {
+        fn new(key: &Key<Self>) -> Self {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(key);
+            EcbCipher { key: bytes }
+        }
+    }
+
+    impl BlockSizeUser for EcbCipher {
+        type BlockSize = U16;
+    }
+
+    impl BlockCipher for EcbCipher {}
+
+    impl BlockEncrypt for EcbCipher {
+        fn encrypt_with_backend(&self, f: impl BlockClosure<BlockSize = U16>) {
+            f.call(&mut EcbBackend { key: self.key });
+        }
+    }
+
+    /// Encrypts blocks one at a time with the installed `AesEcb`, for
+    /// `EcbCipher`'s `BlockEncrypt`
+    struct EcbBackend {
+        key: [u8; 16],
+    }
+
+    impl BlockSizeUser for EcbBackend {
+        type BlockSize = U16;
+    }
+
+    impl ParBlocksSizeUser for EcbBackend {
+        type ParBlocksSize = U1;
+    }
+
+    impl BlockBackend for EcbBackend {
+        fn proc_block(&mut self, mut block: InOut<'_, '_, Block<Self>>) {
+            let mut input = [0; 16];
+            input.copy_from_slice(block.get_in());
+            let result = interrupt::free(|cs| {
+                let mut installed = INSTALLED.borrow(cs).borrow_mut();
+                let ecb = installed
+                    .as_mut()
+                    .expect("no AesEcb installed for EcbCipher");
+                let mut result = ecb.crypt_block(input, self.key);
+                for _ in 0..MAX_RETRIES {
+                    if result.is_ok() {
+                        break;
+                    }
+                    result = ecb.crypt_block(input, self.key);
+                }
+                result
+            });
+            let output = result.expect("ECB operation failed after all retries");
+            block.get_out().copy_from_slice(&output);
+        }
+    }
+}
@@ -11,6 +11,36 @@ pub trait GpioExt {
     fn split(self) -> Self::Parts;
 }
 
+/// A pin in mode `MODE`, as taken by the peripheral constructors
+///
+/// Implemented both by the pins with their number in the type (`PIN0` ...
+/// `PIN31`) and by the erased `PIN`. A peripheral owns the pins it's given
+/// until it is released, so the same pin can't be given to two peripherals.
+/// Passing the numbered pins keeps their types, and `release`/`teardown`
+/// hands them back as they were.
+pub trait PeripheralPin<MODE> {
+    /// Returns the pin number, as written to the peripheral's PSEL registers
+    fn pin_id(&self) -> u8;
+}
+
+/// A pin a peripheral can also do without, like the UART's flow control pins
+///
+/// Implemented by the numbered and erased pins, like `PeripheralPin`, and by
+/// `NoPin`, which stands in for a pin that isn't connected.
+pub trait OptionalPin<MODE> {
+    /// Returns the pin number, or `None` for `NoPin`
+    fn optional_pin_id(&self) -> Option<u8>;
+}
+
+/// No pin, for an `OptionalPin` that isn't connected
+pub struct NoPin;
+
+impl<MODE> OptionalPin<MODE> for NoPin {
+    fn optional_pin_id(&self) -> Option<u8> {
+        None
+    }
+}
+
 /// Input mode (type state)
 pub struct Input<MODE> {
     _mode: PhantomData<MODE>,
@@ -36,8 +66,26 @@ pub struct Output<MODE> {
 /// Push pull output (type state)
 pub struct PushPull;
 
+/// Disconnected input, parked to minimise power consumption (type state)
+pub struct Disconnected;
+
+pub use nrf51::gpio::pin_cnf::{DRIVE_A, PULL_A};
+
+/// A complete pin configuration, as written by `configure_pins`
+#[derive(Clone, Copy)]
+pub struct PinConfig {
+    /// Configures the pin as an output if true, as an input otherwise
+    pub output: bool,
+    /// Drive strength and open drain / source modes (for outputs)
+    pub drive: DRIVE_A,
+    /// Pull resistor
+    pub pull: PULL_A,
+    /// Connects the input buffer, so the pin level can be read
+    pub connect_input: bool,
+}
+
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $PXx:ident, [
+    ($GPIOX:ident, $gpiox:ident, $PXx:ident, $PXn:ident, [
         $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty),)+
     ]) => {
         /// GPIO
@@ -49,8 +97,8 @@ macro_rules! gpio {
             use nrf51::$GPIOX;
 
             use super::{
-                Floating, GpioExt, Input, OpenDrain, Output,
-                PullDown, PullUp, PushPull,
+                Disconnected, Floating, GpioExt, Input, OpenDrain, OptionalPin, Output,
+                PeripheralPin, PinConfig, PullDown, PullUp, PushPull,
             };
 
             /// GPIO parts
@@ -61,6 +109,129 @@ macro_rules! gpio {
                 )+
             }
 
+            impl Parts {
+                /// Drives the output pins in `mask` low with a single register
+                /// write
+                ///
+                /// Bit *n* of `mask` selects pin *n*. This is meant for putting
+                /// a group of control lines into a safe state during start-up,
+                /// right after splitting the port and before any pins are moved
+                /// out of `Parts`. The output level is set whatever the pins'
+                /// configuration, but only shows once they are outputs, so it
+                /// also presets the level they start driving with.
+                pub fn reset_outputs(&mut self, mask: u32) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*GPIO::ptr()).outclr.write(|w| w.bits(mask)) };
+                }
+
+                /// Drives the output pins in `mask` high with a single register
+                /// write
+                ///
+                /// See `reset_outputs()`.
+                pub fn set_outputs(&mut self, mask: u32) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*GPIO::ptr()).outset.write(|w| w.bits(mask)) };
+                }
+            }
+
+            /// Row-at-a-time driver for a multiplexed LED matrix, such as the
+            /// micro:bit's display
+            ///
+            /// Each LED sits between a row and a column pin and lights while
+            /// its row is driven high and its column low. The pin masks are
+            /// computed once in `new`, so refreshing a row takes four
+            /// register writes however many pins are involved, rather than a
+            /// write per pin. Call `refresh_row()` for every row in turn, fast
+            /// enough for the display not to flicker.
+            pub struct PinMatrix<const ROWS: usize, const COLS: usize> {
+                rows: [$PXx<Output<PushPull>>; ROWS],
+                cols: [$PXx<Output<PushPull>>; COLS],
+                row_masks: [u32; ROWS],
+                col_masks: [u32; COLS],
+                all_rows: u32,
+                all_cols: u32,
+            }
+
+            impl<const ROWS: usize, const COLS: usize> PinMatrix<ROWS, COLS> {
+                /// Takes the row and column pins, turning all LEDs off
+                ///
+                /// # Panics
+                ///
+                /// Panics if `COLS` is greater than 32, as `refresh_row()`
+                /// selects the columns with the bits of a `u32`.
+                pub fn new(
+                    rows: [$PXx<Output<PushPull>>; ROWS],
+                    cols: [$PXx<Output<PushPull>>; COLS],
+                ) -> Self {
+                    assert!(COLS <= 32, "a PinMatrix can have at most 32 columns");
+
+                    let mut row_masks = [0; ROWS];
+                    for (mask, pin) in row_masks.iter_mut().zip(rows.iter()) {
+                        *mask = 1 << pin.i;
+                    }
+                    let mut col_masks = [0; COLS];
+                    for (mask, pin) in col_masks.iter_mut().zip(cols.iter()) {
+                        *mask = 1 << pin.i;
+                    }
+
+                    let matrix = PinMatrix {
+                        all_rows: row_masks.iter().fold(0, |all, mask| all | mask),
+                        all_cols: col_masks.iter().fold(0, |all, mask| all | mask),
+                        rows,
+                        cols,
+                        row_masks,
+                        col_masks,
+                    };
+                    matrix.blank();
+                    matrix
+                }
+
+                /// Lights the LEDs of `row` selected by `columns`, turning all
+                /// others off
+                ///
+                /// Bit *n* of `columns` lights the LED in column *n*. All rows
+                /// are deselected before the columns change, so no LED of the
+                /// previous row flashes with the new pattern.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `row` is not less than `ROWS`.
+                pub fn refresh_row(&mut self, row: usize, columns: u32) {
+                    let row_mask = self.row_masks[row];
+                    let mut lit = 0;
+                    for (n, mask) in self.col_masks.iter().enumerate() {
+                        if columns & (1 << n) != 0 {
+                            lit |= mask;
+                        }
+                    }
+
+                    // NOTE(unsafe) atomic writes to stateless registers, only
+                    // touching the matrix's own pins
+                    let gpio = unsafe { &*GPIO::ptr() };
+                    gpio.outclr.write(|w| unsafe { w.bits(self.all_rows) });
+                    gpio.outset.write(|w| unsafe { w.bits(self.all_cols & !lit) });
+                    gpio.outclr.write(|w| unsafe { w.bits(lit) });
+                    gpio.outset.write(|w| unsafe { w.bits(row_mask) });
+                }
+
+                /// Turns all LEDs off
+                pub fn blank(&self) {
+                    // NOTE(unsafe) atomic writes to stateless registers, only
+                    // touching the matrix's own pins
+                    let gpio = unsafe { &*GPIO::ptr() };
+                    gpio.outclr.write(|w| unsafe { w.bits(self.all_rows) });
+                    gpio.outset.write(|w| unsafe { w.bits(self.all_cols) });
+                }
+
+                /// Turns all LEDs off and returns the row and column pins
+                pub fn free(
+                    self,
+                ) -> ([$PXx<Output<PushPull>>; ROWS], [$PXx<Output<PushPull>>; COLS]) {
+                    self.blank();
+                    (self.rows, self.cols)
+                }
+            }
+
             impl GpioExt for $GPIOX {
                 type Parts = Parts;
 
@@ -92,6 +263,21 @@ macro_rules! gpio {
                 }
             }
 
+            impl<MODE> $PXx<Output<MODE>> {
+                /// Reads the actual level of the pin
+                ///
+                /// Unlike `is_set_high()`, which returns the level the pin is
+                /// set to drive, this samples the line itself, so it tells
+                /// whether an open drain line is held low by another device or
+                /// whether a push-pull output is shorted or overdriven. If the
+                /// pin's input buffer is disconnected (as it is for push-pull
+                /// outputs) it is connected for the duration of the read.
+                pub fn read_actual(&self) -> bool {
+                    // NOTE(unsafe) the pin is owned, so nothing else changes its configuration
+                    read_level(unsafe { &*GPIO::ptr() }, self.i)
+                }
+            }
+
             impl<MODE> OutputPin for $PXx<Output<MODE>> {
                 type Error = Infallible;
 
@@ -108,6 +294,19 @@ macro_rules! gpio {
                 }
             }
 
+            impl<MODE> $PXx<Input<MODE>> {
+                /// Reads the pin `samples` times in a row and returns the
+                /// majority level (a tie counts as low)
+                ///
+                /// This is a spin-based majority filter, not a time-based
+                /// debounce: the reads follow each other as fast as the CPU
+                /// can do them, so it only filters glitches shorter than a few
+                /// microseconds. A `samples` of 0 is treated as 1.
+                pub fn is_high_sampled(&self, samples: u8) -> bool {
+                    sample_majority(self.i, samples)
+                }
+            }
+
             impl<MODE> InputPin for $PXx<Input<MODE>> {
                 type Error = Infallible;
 
@@ -122,264 +321,376 @@ macro_rules! gpio {
                     Ok(unsafe { (*GPIO::ptr()).in_.read().bits() & (1 << self.i) == 0 })
                 }
             }
+            impl InputPin for $PXx<Output<OpenDrain>> {
+                type Error = Infallible;
 
-            $(
-                /// Pin
-                pub struct $PXi<MODE> {
-                    _mode: PhantomData<MODE>,
-                }
-
-                impl<MODE> $PXi<MODE> {
-                    /// Configures the pin to operate as a floating input pin
-                    pub fn into_floating_input(
-                        self,
-                    ) -> $PXi<Input<Floating>> {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        pincnf.write(|w| {
-                            w.dir()
-                                .input()
-                                .drive()
-                                .s0s1()
-                                .pull()
-                                .disabled()
-                                .sense()
-                                .disabled()
-                                .input()
-                                .connect()
-                        });
-                        $PXi { _mode: PhantomData }
-                    }
+                #[inline(always)]
+                fn is_high(&self) -> Result<bool, Self::Error> {
+                    self.is_low().map(|v| !v)
+                }
 
-                    /// Configures the pin to operate as a open drain input pin
-                    pub fn into_open_drain_input(
-                        self,
-                    ) -> $PXi<Input<OpenDrain>> {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        pincnf.write(|w| {
-                            w.dir()
-                                .input()
-                                .drive()
-                                .s0d1()
-                                .pull()
-                                .disabled()
-                                .sense()
-                                .disabled()
-                                .input()
-                                .connect()
-                        });
-                        $PXi { _mode: PhantomData }
-                    }
+                #[inline(always)]
+                fn is_low(&self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*GPIO::ptr()).in_.read().bits() & (1 << self.i) == 0 })
+                }
+            }
 
-                    /// Configures the pin to operate as a pulled down input pin
-                    pub fn into_pull_down_input(
-                        self,
-                        ) -> $PXi<Input<PullDown>> {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        pincnf.write(|w| {
-                            w.dir()
-                                .input()
-                                .drive()
-                                .s0s1()
-                                .pull()
-                                .pulldown()
-                                .sense()
-                                .disabled()
-                                .input()
-                                .connect()
-                        });
-
-                        $PXi { _mode: PhantomData }
-                    }
+            /// Pin, with its number as a const parameter
+            ///
+            /// The pins handed out by `split` are available under the
+            /// `PIN0` ... `PIN31` aliases.
+            pub struct $PXn<MODE, const N: u8> {
+                _mode: PhantomData<MODE>,
+            }
 
-                    /// Configures the pin to operate as a pulled up input pin
-                    pub fn into_pull_up_input(
-                        self,
-                    ) -> $PXi<Input<PullUp>> {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        pincnf.write(|w| {
-                            w.dir()
-                                .input()
-                                .drive()
-                                .s0s1()
-                                .pull()
-                                .pullup()
-                                .sense()
-                                .disabled()
-                                .input()
-                                .connect()
-                        });
-
-                        $PXi { _mode: PhantomData }
-                    }
+            impl<MODE, const N: u8> $PXn<MODE, N> {
+                /// Returns the pin number, e.g. to log or check a pin
+                /// assignment without erasing the pin
+                pub fn pin_number(&self) -> u8 {
+                    N
+                }
 
-                    /// Configures the pin to operate as an open drain output pin
-                    pub fn into_open_drain_output(
-                        self,
-                    ) -> $PXi<Output<OpenDrain>> {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        pincnf.write(|w| {
-                            w.dir()
-                                .output()
-                                .drive()
-                                .s0d1()
-                                .pull()
-                                .disabled()
-                                .sense()
-                                .disabled()
-                                .input()
-                                .disconnect()
-                        });
-
-                        $PXi { _mode: PhantomData }
-                    }
+                /// Configures the pin to operate as a floating input pin
+                pub fn into_floating_input(
+                    self,
+                ) -> $PXn<Input<Floating>, N> {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .input()
+                            .drive()
+                            .s0s1()
+                            .pull()
+                            .disabled()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .connect()
+                    });
+                    $PXn { _mode: PhantomData }
+                }
 
-                    /// Configures the pin to operate as an push pull output pin
-                    pub fn into_push_pull_output(
-                        self,
-                    ) -> $PXi<Output<PushPull>> {
-
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        pincnf.write(|w| {
-                            w.dir()
-                                .output()
-                                .drive()
-                                .s0s1()
-                                .pull()
-                                .disabled()
-                                .sense()
-                                .disabled()
-                                .input()
-                                .disconnect()
-                        });
-
-                        $PXi { _mode: PhantomData }
-                    }
+                /// Configures the pin to operate as a open drain input pin
+                pub fn into_open_drain_input(
+                    self,
+                ) -> $PXn<Input<OpenDrain>, N> {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .input()
+                            .drive()
+                            .s0d1()
+                            .pull()
+                            .disabled()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .connect()
+                    });
+                    $PXn { _mode: PhantomData }
                 }
 
-                impl $PXi<Input<OpenDrain>> {
-                    /// Enables / disables the internal pull up
-                    pub fn internal_pull_up(&mut self, on: bool) {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        if on {
-                            pincnf.modify(|_, w| w.pull().pullup());
-                        } else {
-                            pincnf.modify(|_, w| w.pull().disabled());
-                        }
-                    }
+                /// Configures the pin to operate as a pulled down input pin
+                pub fn into_pull_down_input(
+                    self,
+                    ) -> $PXn<Input<PullDown>, N> {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .input()
+                            .drive()
+                            .s0s1()
+                            .pull()
+                            .pulldown()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .connect()
+                    });
+
+                    $PXn { _mode: PhantomData }
                 }
 
-                impl $PXi<Output<OpenDrain>> {
-                    /// Enables / disables the internal pull up
-                    pub fn internal_pull_up(&mut self, on: bool) {
-                        let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[$i] };
-                        if on {
-                            pincnf.modify(|_, w| w.pull().pullup());
-                        } else {
-                            pincnf.modify(|_, w| w.pull().disabled());
-                        }
-                    }
+                /// Configures the pin to operate as a pulled up input pin
+                pub fn into_pull_up_input(
+                    self,
+                ) -> $PXn<Input<PullUp>, N> {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .input()
+                            .drive()
+                            .s0s1()
+                            .pull()
+                            .pullup()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .connect()
+                    });
+
+                    $PXn { _mode: PhantomData }
                 }
 
-                impl<MODE> From<$PXi<Output<MODE>>> for $PXx<Output<MODE>> {
-                    /// Erases the pin number from the type
-                    ///
-                    /// This is useful when you want to collect the pins into an array where you
-                    /// need all the elements to have the same type
-                    #[inline(always)]
-                    fn from(pin: $PXi<Output<MODE>>) -> $PXx<Output<MODE>> {
-                        $PXx {
-                            i: $i,
-                            _mode: pin._mode,
-                        }
-                    }
+                /// Configures the pin to operate as an open drain output pin
+                ///
+                /// The input buffer stays connected so the actual line level
+                /// can be read back through `InputPin`.
+                pub fn into_open_drain_output(
+                    self,
+                ) -> $PXn<Output<OpenDrain>, N> {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .output()
+                            .drive()
+                            .s0d1()
+                            .pull()
+                            .disabled()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .connect()
+                    });
+
+                    $PXn { _mode: PhantomData }
                 }
 
-                impl<MODE> $PXi<Output<MODE>> {
-                    /// Erases the pin number from the type
-                    ///
-                    /// This is useful when you want to collect the pins into an array where you
-                    /// need all the elements to have the same type
-                    #[deprecated(note = "Use `into` instead")]
-                    pub fn downgrade(self) -> $PXx<Output<MODE>> {
-                        $PXx {
-                            i: $i,
-                            _mode: self._mode,
-                        }
+                /// Configures the pin to operate as an push pull output pin
+                pub fn into_push_pull_output(
+                    self,
+                ) -> $PXn<Output<PushPull>, N> {
+
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .output()
+                            .drive()
+                            .s0s1()
+                            .pull()
+                            .disabled()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .disconnect()
+                    });
+
+                    $PXn { _mode: PhantomData }
+                }
+
+                /// Configures the pin as a disconnected input, to park it
+                ///
+                /// The pin is an input with the input buffer disconnected and
+                /// the pull resistor disabled, which is also its state after
+                /// reset. A floating pin with a connected input buffer can draw
+                /// current through the buffer when its level sits between the
+                /// logic thresholds; a disconnected one can't, so this is the
+                /// lowest power state for unused pins.
+                ///
+                /// How much it saves depends on the pin's previous state:
+                ///
+                /// - With a pull resistor enabled and the pin held at the
+                ///   opposite level from outside, the resistor draws VDD / R,
+                ///   about 230µA at 3V with the 13kΩ typical pull resistance
+                ///   of the product specification.
+                /// - With the input buffer connected and the pin floating, the
+                ///   buffer draws current while the level drifts between the
+                ///   thresholds. The product specification gives no figure for
+                ///   this, as it depends on the level; measure it if it
+                ///   matters.
+                /// - With the pin driven firmly high or low from outside and no
+                ///   pull resistor, or already in its reset state, only leakage
+                ///   current flows either way, and disconnecting saves nothing
+                ///   measurable.
+                pub fn into_disconnected(
+                    self,
+                ) -> $PXn<Disconnected, N> {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    pincnf.write(|w| {
+                        w.dir()
+                            .input()
+                            .drive()
+                            .s0s1()
+                            .pull()
+                            .disabled()
+                            .sense()
+                            .disabled()
+                            .input()
+                            .disconnect()
+                    });
+
+                    $PXn { _mode: PhantomData }
+                }
+            }
+
+            impl<const N: u8> $PXn<Input<OpenDrain>, N> {
+                /// Enables / disables the internal pull up
+                pub fn internal_pull_up(&mut self, on: bool) {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    if on {
+                        pincnf.modify(|_, w| w.pull().pullup());
+                    } else {
+                        pincnf.modify(|_, w| w.pull().disabled());
                     }
                 }
+            }
 
-                impl<MODE> StatefulOutputPin for $PXi<Output<MODE>> {
-                    #[inline(always)]
-                    fn is_set_high(&self) -> Result<bool, Self::Error> {
-                        self.is_set_low().map(|v| !v)
+            impl<const N: u8> $PXn<Output<OpenDrain>, N> {
+                /// Enables / disables the internal pull up
+                pub fn internal_pull_up(&mut self, on: bool) {
+                    let pincnf = unsafe { &(*GPIO::ptr()).pin_cnf[N as usize] };
+                    if on {
+                        pincnf.modify(|_, w| w.pull().pullup());
+                    } else {
+                        pincnf.modify(|_, w| w.pull().disabled());
                     }
+                }
+            }
 
-                    #[inline(always)]
-                    fn is_set_low(&self) -> Result<bool, Self::Error> {
-                        // NOTE(unsafe) atomic read with no side effects
-                        Ok(unsafe { (*GPIO::ptr()).out.read().bits() & (1 << $i) == 0 })
+            impl<MODE, const N: u8> From<$PXn<Output<MODE>, N>> for $PXx<Output<MODE>> {
+                /// Erases the pin number from the type
+                ///
+                /// This is useful when you want to collect the pins into an array where you
+                /// need all the elements to have the same type
+                #[inline(always)]
+                fn from(pin: $PXn<Output<MODE>, N>) -> $PXx<Output<MODE>> {
+                    $PXx {
+                        i: N,
+                        _mode: pin._mode,
                     }
                 }
+            }
 
-                impl<MODE> OutputPin for $PXi<Output<MODE>> {
-                    type Error = Infallible;
+            impl<MODE, const N: u8> $PXn<Output<MODE>, N> {
+                /// Reads the actual level of the pin
+                ///
+                /// Unlike `is_set_high()`, which returns the level the pin is
+                /// set to drive, this samples the line itself, so it tells
+                /// whether an open drain line is held low by another device or
+                /// whether a push-pull output is shorted or overdriven. If the
+                /// pin's input buffer is disconnected (as it is for push-pull
+                /// outputs) it is connected for the duration of the read.
+                pub fn read_actual(&self) -> bool {
+                    // NOTE(unsafe) the pin is owned, so nothing else changes its configuration
+                    read_level(unsafe { &*GPIO::ptr() }, N)
+                }
 
-                    #[inline(always)]
-                    fn set_high(&mut self) -> Result<(), Self::Error> {
-                        // NOTE(unsafe) atomic write to a stateless register
-                        Ok(unsafe { (*GPIO::ptr()).outset.write(|w| w.bits(1 << $i)) })
+                /// Erases the pin number from the type
+                ///
+                /// This is useful when you want to collect the pins into an array where you
+                /// need all the elements to have the same type
+                #[deprecated(note = "Use `into` instead")]
+                pub fn downgrade(self) -> $PXx<Output<MODE>> {
+                    $PXx {
+                        i: N,
+                        _mode: self._mode,
                     }
+                }
+            }
 
-                    #[inline(always)]
-                    fn set_low(&mut self) -> Result<(), Self::Error> {
-                        // NOTE(unsafe) atomic write to a stateless register
-                        Ok(unsafe { (*GPIO::ptr()).outclr.write(|w| w.bits(1 << $i)) })
-                    }
+            impl<MODE, const N: u8> StatefulOutputPin for $PXn<Output<MODE>, N> {
+                #[inline(always)]
+                fn is_set_high(&self) -> Result<bool, Self::Error> {
+                    self.is_set_low().map(|v| !v)
                 }
 
-                impl<MODE> From<$PXi<Input<MODE>>> for $PXx<Input<MODE>> {
-                    /// Erases the pin number from the type
-                    ///
-                    /// This is useful when you want to collect the pins into an array where you
-                    /// need all the elements to have the same type
-                    #[inline(always)]
-                    fn from(pin: $PXi<Input<MODE>>) -> $PXx<Input<MODE>> {
-                        $PXx {
-                            i: $i,
-                            _mode: pin._mode,
-                        }
-                    }
+                #[inline(always)]
+                fn is_set_low(&self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*GPIO::ptr()).out.read().bits() & (1 << N) == 0 })
                 }
+            }
 
-                impl<MODE> $PXi<Input<MODE>> {
-                    /// Erases the pin number from the type
-                    ///
-                    /// This is useful when you want to collect the pins into an array where you
-                    /// need all the elements to have the same type
-                    #[deprecated(note = "Use `into` instead")]
-                    #[inline(always)]
-                    pub fn downgrade(self) -> $PXx<Input<MODE>> {
-                        $PXx {
-                            i: $i,
-                            _mode: self._mode,
-                        }
-                    }
+            impl<MODE, const N: u8> OutputPin for $PXn<Output<MODE>, N> {
+                type Error = Infallible;
+
+                #[inline(always)]
+                fn set_high(&mut self) -> Result<(), Self::Error> {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    Ok(unsafe { (*GPIO::ptr()).outset.write(|w| w.bits(1 << N)) })
                 }
 
-                impl<MODE> InputPin for $PXi<Input<MODE>> {
-                    type Error = Infallible;
+                #[inline(always)]
+                fn set_low(&mut self) -> Result<(), Self::Error> {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    Ok(unsafe { (*GPIO::ptr()).outclr.write(|w| w.bits(1 << N)) })
+                }
+            }
 
-                    #[inline(always)]
-                    fn is_high(&self) -> Result<bool, Self::Error> {
-                        self.is_low().map(|v| !v)
+            impl<MODE, const N: u8> From<$PXn<Input<MODE>, N>> for $PXx<Input<MODE>> {
+                /// Erases the pin number from the type
+                ///
+                /// This is useful when you want to collect the pins into an array where you
+                /// need all the elements to have the same type
+                #[inline(always)]
+                fn from(pin: $PXn<Input<MODE>, N>) -> $PXx<Input<MODE>> {
+                    $PXx {
+                        i: N,
+                        _mode: pin._mode,
                     }
+                }
+            }
+
+            impl<MODE, const N: u8> $PXn<Input<MODE>, N> {
+                /// Reads the pin `samples` times in a row and returns the
+                /// majority level (a tie counts as low)
+                ///
+                /// This is a spin-based majority filter, not a time-based
+                /// debounce: the reads follow each other as fast as the CPU
+                /// can do them, so it only filters glitches shorter than a few
+                /// microseconds. A `samples` of 0 is treated as 1.
+                pub fn is_high_sampled(&self, samples: u8) -> bool {
+                    sample_majority(N, samples)
+                }
 
-                    #[inline(always)]
-                    fn is_low(&self) -> Result<bool, Self::Error> {
-                        // NOTE(unsafe) atomic read with no side effects
-                       Ok(unsafe { (*GPIO::ptr()).in_.read().bits() & (1 << $i) == 0 })
+                /// Erases the pin number from the type
+                ///
+                /// This is useful when you want to collect the pins into an array where you
+                /// need all the elements to have the same type
+                #[deprecated(note = "Use `into` instead")]
+                #[inline(always)]
+                pub fn downgrade(self) -> $PXx<Input<MODE>> {
+                    $PXx {
+                        i: N,
+                        _mode: self._mode,
                     }
                 }
+            }
+
+            impl<MODE, const N: u8> InputPin for $PXn<Input<MODE>, N> {
+                type Error = Infallible;
+
+                #[inline(always)]
+                fn is_high(&self) -> Result<bool, Self::Error> {
+                    self.is_low().map(|v| !v)
+                }
+
+                #[inline(always)]
+                fn is_low(&self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                   Ok(unsafe { (*GPIO::ptr()).in_.read().bits() & (1 << N) == 0 })
+                }
+            }
+
+            impl<const N: u8> InputPin for $PXn<Output<OpenDrain>, N> {
+                type Error = Infallible;
+
+                #[inline(always)]
+                fn is_high(&self) -> Result<bool, Self::Error> {
+                    self.is_low().map(|v| !v)
+                }
+
+                #[inline(always)]
+                fn is_low(&self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                   Ok(unsafe { (*GPIO::ptr()).in_.read().bits() & (1 << N) == 0 })
+                }
+            }
+
+            $(
+                /// Pin
+                pub type $PXi<MODE> = $PXn<MODE, { $i }>;
             )+
 
                 impl<TYPE> $PXx<TYPE> {
@@ -388,11 +699,116 @@ macro_rules! gpio {
                         self.i
                     }
                 }
+
+            impl<MODE> PeripheralPin<MODE> for $PXx<MODE> {
+                fn pin_id(&self) -> u8 {
+                    self.i
+                }
+            }
+
+            impl<MODE, const N: u8> PeripheralPin<MODE> for $PXn<MODE, N> {
+                fn pin_id(&self) -> u8 {
+                    N
+                }
+            }
+
+            impl<MODE> OptionalPin<MODE> for $PXx<MODE> {
+                fn optional_pin_id(&self) -> Option<u8> {
+                    Some(self.i)
+                }
+            }
+
+            impl<MODE, const N: u8> OptionalPin<MODE> for $PXn<MODE, N> {
+                fn optional_pin_id(&self) -> Option<u8> {
+                    Some(N)
+                }
+            }
+
+            /// Writes the same configuration to several pins in a tight loop
+            ///
+            /// This is meant for parallel buses, where every data line needs
+            /// identical drive and pull settings. Like `read_pin` it bypasses
+            /// the type-state pin model: no pins are consumed or returned, so
+            /// only use it on pins no other code owns, e.g. before splitting the
+            /// port (the split pins' types don't reflect the new configuration).
+            ///
+            /// # Panics
+            ///
+            /// Panics if an index is not a valid pin number (0 ..= 31), before
+            /// any pin is configured.
+            pub fn configure_pins(port: &$GPIOX, indices: &[u8], config: PinConfig) {
+                assert!(indices.iter().all(|&i| i < 32), "pin index out of range");
+                for &index in indices {
+                    port.pin_cnf[index as usize].write(|w| {
+                        let w = if config.output {
+                            w.dir().output()
+                        } else {
+                            w.dir().input()
+                        };
+                        let w = if config.connect_input {
+                            w.input().connect()
+                        } else {
+                            w.input().disconnect()
+                        };
+                        w.drive()
+                            .variant(config.drive)
+                            .pull()
+                            .variant(config.pull)
+                            .sense()
+                            .disabled()
+                    });
+                }
+            }
+
+            /// Reads the level of a single pin without splitting the port
+            ///
+            /// This bypasses the type-state pin model and is meant for one-off
+            /// reads during initialisation, e.g. of a boot-mode strapping pin,
+            /// before the port is split. If the pin's input buffer is
+            /// disconnected (as it is after reset) it is connected for the
+            /// duration of the read and disconnected again afterwards.
+            ///
+            /// Nothing stops other code from reconfiguring the pin at the same
+            /// time (e.g. from an interrupt handler owning an already split pin),
+            /// so only use this while nothing else can touch it.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `index` is not a valid pin number (0 ..= 31).
+            pub fn read_pin(port: &$GPIOX, index: u8) -> bool {
+                assert!(index < 32, "pin index out of range");
+                read_level(port, index)
+            }
+
+            fn sample_majority(index: u8, samples: u8) -> bool {
+                let samples = samples.max(1);
+                let mut high = 0;
+                for _ in 0..samples {
+                    // NOTE(unsafe) atomic read with no side effects
+                    if unsafe { (*GPIO::ptr()).in_.read().bits() } & (1 << index) != 0 {
+                        high += 1;
+                    }
+                }
+                high * 2 > u32::from(samples)
+            }
+
+            fn read_level(port: &nrf51::gpio::RegisterBlock, index: u8) -> bool {
+                let pincnf = &port.pin_cnf[index as usize];
+                let disconnected = pincnf.read().input().is_disconnect();
+                if disconnected {
+                    pincnf.modify(|_, w| w.input().connect());
+                }
+                let high = port.in_.read().bits() & (1 << index) != 0;
+                if disconnected {
+                    pincnf.modify(|_, w| w.input().disconnect());
+                }
+                high
+            }
         }
     }
 }
 
-gpio!(GPIO, gpio, PIN, [
+gpio!(GPIO, gpio, PIN, Pin, [
     PIN0: (pin0, 0, Input<Floating>),
     PIN1: (pin1, 1, Input<Floating>),
     PIN2: (pin2, 2, Input<Floating>),
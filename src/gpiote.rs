@@ -0,0 +1,342 @@
+//! Async edge detection on GPIO pins, using the GPIOTE peripheral.
+//!
+//! Only available with the `async` feature.
+//!
+//! Each of the four GPIOTE channels can watch one pin, so up to four waits
+//! can be in progress at the same time. `Gpiote::new()` hands out the
+//! channels as tokens, and every wait borrows one mutably until the returned
+//! future completes or is dropped, so a channel can't be used by two waits
+//! at once.
+//!
+//! `Gpiote::wait_pin()` combines an input pin and a channel into a
+//! `WaitPin`, which implements the `embedded_hal_async::digital::Wait` trait:
+//!
+//! ```ignore
+//! use embedded_hal_async::digital::Wait;
+//! let (gpiote, mut channels) = Gpiote::new(p.GPIOTE);
+//! let mut button = gpiote.wait_pin(&mut channels.ch0, &button_pin);
+//! button.wait_for_falling_edge().await.unwrap();
+//! ```
+//!
+//! The GPIOTE interrupt handler must call `on_interrupt()` to wake the
+//! waiting tasks, and the interrupt must be unmasked in the NVIC:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn GPIOTE() {
+//!     nrf51_hal::gpiote::on_interrupt();
+//! }
+//! ```
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::{poll_fn, Future};
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal_async::digital::Wait;
+use nrf51::GPIOTE;
+
+use crate::gpio::gpio::PIN;
+use crate::gpio::Input;
+
+/// One of the GPIOTE channels.
+///
+/// There is exactly one token per channel, handed out by `Gpiote::new()` and
+/// taken back by `Gpiote::free()`.
+#[derive(Debug)]
+pub struct GpioteChannel {
+    index: usize,
+}
+
+impl GpioteChannel {
+    /// Returns the channel number, 0 ..= 3.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// The four GPIOTE channels, as returned by `Gpiote::new()`.
+#[derive(Debug)]
+pub struct Channels {
+    pub ch0: GpioteChannel,
+    pub ch1: GpioteChannel,
+    pub ch2: GpioteChannel,
+    pub ch3: GpioteChannel,
+}
+
+/// The pin transition to wait for.
+#[derive(Copy, Clone, Debug)]
+pub enum Edge {
+    /// Low to high
+    Rising,
+    /// High to low
+    Falling,
+    /// Either direction
+    Both,
+}
+
+/// Tasks waiting for each channel's IN event, woken by `on_interrupt`
+static WAKERS: Mutex<RefCell<[Option<Waker>; 4]>> =
+    Mutex::new(RefCell::new([None, None, None, None]));
+
+/// Handles the GPIOTE interrupt.
+///
+/// Call this from the GPIOTE interrupt handler. It disables the interrupt
+/// for every channel whose IN event has been generated and wakes the task
+/// waiting on it, which then clears the event.
+pub fn on_interrupt() {
+    // NOTE(unsafe) only accesses the registers of the channels that fired
+    let gpiote = unsafe { &*GPIOTE::ptr() };
+    interrupt::free(|cs| {
+        let mut wakers = WAKERS.borrow(cs).borrow_mut();
+        for (channel, waker) in wakers.iter_mut().enumerate() {
+            if gpiote.events_in[channel].read().bits() != 0 {
+                gpiote.intenclr.write(|w| unsafe { w.bits(1 << channel) });
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    });
+}
+
+/// A safe wrapper around the GPIOTE peripheral, for waiting on pin edges.
+pub struct Gpiote {
+    gpiote: GPIOTE,
+}
+
+impl Gpiote {
+    /// Takes ownership of the GPIOTE peripheral, returning a safe wrapper and
+    /// its four channels.
+    ///
+    /// Disables all channels and their interrupts.
+    pub fn new(gpiote: GPIOTE) -> (Gpiote, Channels) {
+        gpiote.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        for channel in 0..4 {
+            gpiote.config[channel].reset();
+            gpiote.events_in[channel].reset();
+        }
+        let channels = Channels {
+            ch0: GpioteChannel { index: 0 },
+            ch1: GpioteChannel { index: 1 },
+            ch2: GpioteChannel { index: 2 },
+            ch3: GpioteChannel { index: 3 },
+        };
+        (Gpiote { gpiote }, channels)
+    }
+
+    /// Returns the underlying `nrf51::GPIOTE` instance.
+    ///
+    /// Takes the channels back too, so a `Gpiote` created again from the
+    /// returned instance hands out the only tokens.
+    pub fn free(self, _channels: Channels) -> GPIOTE {
+        self.gpiote
+    }
+
+    /// Returns a future completing on the next `edge` of `pin`.
+    ///
+    /// `channel` is used to watch the pin, and stays borrowed, until the
+    /// future completes or is dropped. Edges before the future is first
+    /// polled aren't seen.
+    pub fn wait_for_edge<'a, MODE>(
+        &'a self,
+        channel: &'a mut GpioteChannel,
+        pin: &'a PIN<Input<MODE>>,
+        edge: Edge,
+    ) -> EdgeFuture<'a> {
+        EdgeFuture {
+            gpiote: &self.gpiote,
+            channel: channel.index,
+            _channel: PhantomData,
+            pin: pin.get_id(),
+            edge,
+            armed: false,
+        }
+    }
+
+    /// Returns a future completing on the next rising edge of `pin`.
+    pub fn wait_for_rising_edge<'a, MODE>(
+        &'a self,
+        channel: &'a mut GpioteChannel,
+        pin: &'a PIN<Input<MODE>>,
+    ) -> EdgeFuture<'a> {
+        self.wait_for_edge(channel, pin, Edge::Rising)
+    }
+
+    /// Returns a future completing on the next falling edge of `pin`.
+    pub fn wait_for_falling_edge<'a, MODE>(
+        &'a self,
+        channel: &'a mut GpioteChannel,
+        pin: &'a PIN<Input<MODE>>,
+    ) -> EdgeFuture<'a> {
+        self.wait_for_edge(channel, pin, Edge::Falling)
+    }
+
+    /// Returns a future completing on the next edge of `pin`, in either
+    /// direction.
+    pub fn wait_for_any_edge<'a, MODE>(
+        &'a self,
+        channel: &'a mut GpioteChannel,
+        pin: &'a PIN<Input<MODE>>,
+    ) -> EdgeFuture<'a> {
+        self.wait_for_edge(channel, pin, Edge::Both)
+    }
+
+    /// Returns a handle implementing `Wait` for `pin`, watching it through
+    /// `channel`.
+    ///
+    /// The channel stays borrowed for as long as the handle lives, but is
+    /// only configured while one of its waits is in progress.
+    pub fn wait_pin<'a, MODE>(
+        &'a self,
+        channel: &'a mut GpioteChannel,
+        pin: &'a PIN<Input<MODE>>,
+    ) -> WaitPin<'a, MODE> {
+        WaitPin {
+            gpiote: self,
+            channel,
+            pin,
+        }
+    }
+}
+
+/// An input pin and a GPIOTE channel, for use through the
+/// `embedded_hal_async::digital::Wait` trait.
+///
+/// Returned by `Gpiote::wait_pin()`. The waits are those of
+/// `Gpiote::wait_for_edge()`, so edges happening while no wait is in
+/// progress aren't seen. `wait_for_high()` and `wait_for_low()` complete at
+/// once if the pin is already at the requested level.
+pub struct WaitPin<'a, MODE> {
+    gpiote: &'a Gpiote,
+    channel: &'a mut GpioteChannel,
+    pin: &'a PIN<Input<MODE>>,
+}
+
+impl<'a, MODE> WaitPin<'a, MODE> {
+    async fn wait_for_level(&mut self, high: bool) {
+        let pin = self.pin;
+        let edge = if high { Edge::Rising } else { Edge::Falling };
+        let mut wait = self.gpiote.wait_for_edge(&mut *self.channel, pin, edge);
+        // Check the level after every poll of the edge wait, so a change
+        // between the check and arming the channel isn't missed
+        poll_fn(|cx| {
+            if Pin::new(&mut wait).poll(cx).is_ready() || pin.is_high() == Ok(high) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    async fn wait_for(&mut self, edge: Edge) {
+        self.gpiote
+            .wait_for_edge(&mut *self.channel, self.pin, edge)
+            .await
+    }
+}
+
+impl<'a, MODE> embedded_hal_1::digital::ErrorType for WaitPin<'a, MODE> {
+    type Error = Infallible;
+}
+
+impl<'a, MODE> Wait for WaitPin<'a, MODE> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Edge::Rising).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Edge::Falling).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Edge::Both).await;
+        Ok(())
+    }
+}
+
+/// A wait for a pin edge, started by `Gpiote::wait_for_edge`.
+pub struct EdgeFuture<'a> {
+    gpiote: &'a GPIOTE,
+    channel: usize,
+    /// Keeps the channel borrowed for as long as the wait
+    _channel: PhantomData<&'a mut GpioteChannel>,
+    pin: u8,
+    edge: Edge,
+    armed: bool,
+}
+
+impl<'a> EdgeFuture<'a> {
+    fn disarm(&mut self) {
+        self.gpiote
+            .intenclr
+            .write(|w| unsafe { w.bits(1 << self.channel) });
+        self.gpiote.config[self.channel].reset();
+        self.gpiote.events_in[self.channel].reset();
+        self.armed = false;
+    }
+}
+
+impl<'a> Future for EdgeFuture<'a> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let channel = this.channel;
+
+        if !this.armed {
+            let (pin, edge) = (this.pin, this.edge);
+            this.gpiote.events_in[channel].reset();
+            this.gpiote.config[channel].write(|w| {
+                let w = unsafe { w.mode().event().psel().bits(pin) };
+                match edge {
+                    Edge::Rising => w.polarity().lo_to_hi(),
+                    Edge::Falling => w.polarity().hi_to_lo(),
+                    Edge::Both => w.polarity().toggle(),
+                }
+            });
+            this.armed = true;
+        }
+
+        // Register before checking the event, so an edge arriving in
+        // between still wakes the task
+        interrupt::free(|cs| {
+            WAKERS.borrow(cs).borrow_mut()[channel] = Some(cx.waker().clone());
+        });
+
+        if this.gpiote.events_in[channel].read().bits() != 0 {
+            this.disarm();
+            Poll::Ready(())
+        } else {
+            this.gpiote
+                .intenset
+                .write(|w| unsafe { w.bits(1 << channel) });
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a> Drop for EdgeFuture<'a> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.disarm();
+        }
+    }
+}
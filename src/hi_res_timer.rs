@@ -9,11 +9,14 @@
 
 use core::marker::PhantomData;
 use core::ops::Deref;
+use core::time::Duration;
 
 use cast::{u16, u32, u8};
 
 use nrf51;
 
+use crate::time::Hfticks;
+
 /// The base frequency of a TIMER, in megahertz.
 ///
 /// This is the highest available frequency.
@@ -28,6 +31,10 @@ pub trait TimerWidth: Into<u32> {
     #[doc(hidden)]
     /// Checked conversion of a u32 to this type.
     fn try_from_u32(val: u32) -> Result<Self, ()>;
+
+    #[doc(hidden)]
+    /// The largest counter value representable in this width.
+    fn max_value() -> u32;
 }
 
 impl TimerWidth for u8 {
@@ -40,6 +47,11 @@ impl TimerWidth for u8 {
     fn try_from_u32(val: u32) -> Result<u8, ()> {
         u8(val).map_err(|_| ())
     }
+
+    #[doc(hidden)]
+    fn max_value() -> u32 {
+        u8::MAX as u32
+    }
 }
 
 impl TimerWidth for u16 {
@@ -52,6 +64,11 @@ impl TimerWidth for u16 {
     fn try_from_u32(val: u32) -> Result<u16, ()> {
         u16(val).map_err(|_| ())
     }
+
+    #[doc(hidden)]
+    fn max_value() -> u32 {
+        u16::MAX as u32
+    }
 }
 
 impl TimerWidth for u32 {
@@ -64,6 +81,11 @@ impl TimerWidth for u32 {
     fn try_from_u32(val: u32) -> Result<u32, ()> {
         Ok(val)
     }
+
+    #[doc(hidden)]
+    fn max_value() -> u32 {
+        u32::MAX
+    }
 }
 
 /// One of the nRF51's high-resolution timers (`nrf51::TIMER0`, `nrf51::TIMER1`,
@@ -240,24 +262,60 @@ impl TimerFrequency {
 
 pub struct HiResTimer<T: Nrf51Timer, Width: TimerWidth> {
     timer: T,
+    saved: Option<SavedConfig>,
     _width: PhantomData<Width>,
 }
 
+/// The error returned by the `HiResTimer` methods converting the counter to a
+/// `Duration` while the TIMER is in counter mode.
+#[derive(Copy, Clone, Debug)]
+pub struct CounterMode;
+
+/// The configuration saved by `HiResTimer::shut_down()`.
+struct SavedConfig {
+    prescaler: u32,
+    shorts: u32,
+    cc: [u32; 4],
+}
+
 impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
     // Private so that TIMER1 and TIMER2 can't be constructed with width u32.
     fn new(timer: T) -> HiResTimer<T, Width> {
         let mut h = HiResTimer {
             timer,
+            saved: None,
             _width: PhantomData,
         };
         // Note prescaler and bitmode must be changed only when the timer is
         // stopped.
         h.stop();
         h.clear();
-        h.timer.mode.reset();
+        // The TIMER may have been left configured by other code (e.g. a
+        // previous HiResTimer given back by `free()`), so the mode, bit-width,
+        // frequency, shortcuts, CC registers, COMPARE events and interrupts
+        // are all written here, with their reset values except for the mode
+        // and bit-width. PPI channels using the TIMER aren't touched.
+        h.timer.mode.write(|w| w.mode().timer());
         Width::program_timer(&h.timer);
         h.timer.prescaler.reset();
-        h.timer.shorts.reset();
+        h.timer.shorts.write(|w| {
+            w.compare0_clear()
+                .disabled()
+                .compare1_clear()
+                .disabled()
+                .compare2_clear()
+                .disabled()
+                .compare3_clear()
+                .disabled()
+                .compare0_stop()
+                .disabled()
+                .compare1_stop()
+                .disabled()
+                .compare2_stop()
+                .disabled()
+                .compare3_stop()
+                .disabled()
+        });
         h.timer.cc[0].reset();
         h.timer.cc[1].reset();
         h.timer.cc[2].reset();
@@ -287,6 +345,18 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
         self.timer
     }
 
+    /// Returns true if the TIMER is in counter mode, reading back the MODE
+    /// register.
+    ///
+    /// A `HiResTimer` always puts the TIMER in timer mode, so this returns
+    /// false unless the MODE register was changed behind its back. In counter
+    /// mode the counter counts COUNT tasks rather than ticks of the prescaled
+    /// clock, so `elapsed_since_clear()` and `max_interval()` return
+    /// `CounterMode` instead of a `Duration`.
+    pub fn is_counter_mode(&self) -> bool {
+        self.timer.mode.read().mode().is_counter()
+    }
+
     /// Returns the TIMER's current frequency.
     pub fn frequency(&self) -> TimerFrequency {
         let prescaler = self.timer.prescaler.read().prescaler().bits();
@@ -294,10 +364,14 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
     }
 
     /// Stops the TIMER and sets its frequency.
+    ///
+    /// The prescaler must only be changed while the TIMER is stopped, so the
+    /// TIMER is stopped first and left stopped.
     pub fn set_frequency(&mut self, frequency: TimerFrequency) {
-        let prescaler = frequency.as_prescaler();
         self.stop();
-        self.timer.prescaler.write(|w| unsafe { w.bits(prescaler) });
+        self.timer
+            .prescaler
+            .write(|w| unsafe { w.bits(frequency.as_prescaler()) });
     }
 
     /// Resets the TIMER's counter to zero.
@@ -307,6 +381,55 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
         self.timer.tasks_clear.write(|w| unsafe { w.bits(1) });
     }
 
+    /// Returns the time counted since the counter was last cleared.
+    ///
+    /// Captures the counter into the specified CC register, overwriting
+    /// whatever was stored there, and converts it using the TIMER's current
+    /// frequency. Pick a register that isn't used for a compare, like with
+    /// `SharedDelayTimer`. Rounded down to the nanosecond; at 16MHz the
+    /// resolution is 62.5ns.
+    ///
+    /// The counter wraps to zero after `max_interval()`, so longer intervals
+    /// are returned modulo that. The result is only unambiguous if the caller
+    /// knows less than `max_interval()` has passed since `clear()`.
+    ///
+    /// The time the TIMER spends stopped isn't counted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CounterMode` if the TIMER is in counter mode, see
+    /// `is_counter_mode()`.
+    pub fn elapsed_since_clear(&mut self, register: TimerCc) -> Result<Duration, CounterMode> {
+        self.capture(register);
+        // Mask off anything above the bit-width, which isn't part of the count
+        let ticks = self.captured_counter(register) & Width::max_value();
+        self.ticks_to_duration(u64::from(ticks))
+    }
+
+    /// Returns the longest interval `elapsed_since_clear()` can measure at
+    /// the TIMER's current frequency and bit-width.
+    ///
+    /// This is the time the counter takes to wrap. Rounded down to the
+    /// nanosecond.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CounterMode` if the TIMER is in counter mode, see
+    /// `is_counter_mode()`.
+    pub fn max_interval(&self) -> Result<Duration, CounterMode> {
+        self.ticks_to_duration(u64::from(Width::max_value()) + 1)
+    }
+
+    /// Converts a tick count at the TIMER's current frequency to a
+    /// `Duration`, rounded down to the nanosecond.
+    fn ticks_to_duration(&self, ticks: u64) -> Result<Duration, CounterMode> {
+        if self.is_counter_mode() {
+            return Err(CounterMode);
+        }
+        // Each tick is 2^prescaler cycles of the 16MHz base clock
+        Ok(Hfticks(ticks << self.frequency().as_prescaler()).to_duration())
+    }
+
     /// Starts the TIMER.
     pub fn start(&mut self) {
         self.timer.tasks_start.write(|w| unsafe { w.bits(1) });
@@ -321,10 +444,47 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
     ///
     /// This lowers power consumption, but increases the latency of the next
     /// start.
+    ///
+    /// The configuration (bit-width, frequency, shortcuts, and CC registers)
+    /// is saved first; call `power_up()` before using the TIMER again to make
+    /// sure it is in effect.
     pub fn shut_down(&mut self) {
+        self.saved = Some(SavedConfig {
+            prescaler: self.timer.prescaler.read().bits(),
+            shorts: self.timer.shorts.read().bits(),
+            cc: [
+                self.timer.cc[0].read().bits(),
+                self.timer.cc[1].read().bits(),
+                self.timer.cc[2].read().bits(),
+                self.timer.cc[3].read().bits(),
+            ],
+        });
         self.timer.tasks_shutdown.write(|w| unsafe { w.bits(1) });
     }
 
+    /// Restores the configuration saved by `shut_down()`.
+    ///
+    /// Re-applies timer mode, the bit-width, frequency, shortcuts, and CC
+    /// registers, and resets the counter to zero. The TIMER is left stopped;
+    /// call `start()` to resume counting.
+    ///
+    /// Does nothing if the TIMER hasn't been shut down.
+    pub fn power_up(&mut self) {
+        if let Some(saved) = self.saved.take() {
+            self.stop();
+            self.clear();
+            self.timer.mode.write(|w| w.mode().timer());
+            Width::program_timer(&self.timer);
+            self.timer
+                .prescaler
+                .write(|w| unsafe { w.bits(saved.prescaler) });
+            self.timer.shorts.write(|w| unsafe { w.bits(saved.shorts) });
+            for (cc, value) in self.timer.cc.iter().zip(saved.cc.iter()) {
+                cc.write(|w| unsafe { w.bits(*value) });
+            }
+        }
+    }
+
     /// Stores a value in the specified CC register.
     pub fn set_compare_register(&mut self, register: TimerCc, ticks: Width) {
         self.timer.cc[register as usize].write(|w| unsafe { w.bits(ticks.into()) });
@@ -335,6 +495,30 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
         self.timer.tasks_capture[register as usize].write(|w| unsafe { w.bits(1) });
     }
 
+    /// Stores the current counter value in all four CC registers.
+    ///
+    /// The four CAPTURE tasks are triggered in immediate succession, so the
+    /// captured values are as close together as the CPU can make them. Read
+    /// them back with `captured_all()`.
+    pub fn capture_all(&mut self) {
+        let tasks = &self.timer.tasks_capture;
+        tasks[0].write(|w| unsafe { w.bits(1) });
+        tasks[1].write(|w| unsafe { w.bits(1) });
+        tasks[2].write(|w| unsafe { w.bits(1) });
+        tasks[3].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Returns the values currently stored in the four CC registers, indexed
+    /// by CC register number.
+    pub fn captured_all(&mut self) -> [u32; 4] {
+        [
+            self.timer.cc[0].read().bits(),
+            self.timer.cc[1].read().bits(),
+            self.timer.cc[2].read().bits(),
+            self.timer.cc[3].read().bits(),
+        ]
+    }
+
     /// Returns the value currently stored in the specified CC register.
     ///
     /// Returns a u32. The value is expected to be small enough to fit in this
@@ -352,6 +536,14 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
         event_reg.read().bits() != 0
     }
 
+    /// Returns the address of the COMPARE event register for the specified CC
+    /// register.
+    ///
+    /// This is the value to use as a PPI channel's event end point.
+    pub fn compare_event_address(&self, register: TimerCc) -> u32 {
+        &self.timer.events_compare[register as usize] as *const _ as u32
+    }
+
     /// Clears the COMPARE event flag for the specified CC register.
     pub fn clear_compare_event(&mut self, register: TimerCc) {
         let event_reg = &self.timer.events_compare[register as usize];
@@ -396,50 +588,79 @@ impl<T: Nrf51Timer, Width: TimerWidth> HiResTimer<T, Width> {
         });
     }
 
+    /// Returns true if the shortcut between the specified CC register's
+    /// COMPARE event and the CLEAR task is enabled.
+    pub fn is_auto_clear_enabled(&self, register: TimerCc) -> bool {
+        let shorts = self.timer.shorts.read();
+        match register {
+            TimerCc::CC0 => shorts.compare0_clear().is_enabled(),
+            TimerCc::CC1 => shorts.compare1_clear().is_enabled(),
+            TimerCc::CC2 => shorts.compare2_clear().is_enabled(),
+            TimerCc::CC3 => shorts.compare3_clear().is_enabled(),
+        }
+    }
+
+    /// Enables or disables one shortcut, leaving the others unchanged.
+    fn set_shortcut(&mut self, register: TimerCc, task: Shortcut, enabled: bool) {
+        self.timer
+            .shorts
+            .modify(|r, w| unsafe { w.bits(with_shortcut(r.bits(), register, task, enabled)) });
+    }
+
     /// Enables the shortcut between a COMPARE event and the CLEAR task.
     ///
     /// When the counter reaches the value in the specified CC register, the
     /// counter will be automatically reset to zero.
+    ///
+    /// The other shortcuts are left unchanged.
     pub fn enable_auto_clear(&mut self, register: TimerCc) {
-        self.timer.shorts.write(|w| match register {
-            TimerCc::CC0 => w.compare0_clear().enabled(),
-            TimerCc::CC1 => w.compare1_clear().enabled(),
-            TimerCc::CC2 => w.compare2_clear().enabled(),
-            TimerCc::CC3 => w.compare3_clear().enabled(),
-        });
+        self.set_shortcut(register, Shortcut::Clear, true);
     }
 
     /// Disables the shortcut between a COMPARE event and the CLEAR task.
+    ///
+    /// The other shortcuts are left unchanged.
     pub fn disable_auto_clear(&mut self, register: TimerCc) {
-        self.timer.shorts.write(|w| match register {
-            TimerCc::CC0 => w.compare0_clear().disabled(),
-            TimerCc::CC1 => w.compare1_clear().disabled(),
-            TimerCc::CC2 => w.compare2_clear().disabled(),
-            TimerCc::CC3 => w.compare3_clear().disabled(),
-        });
+        self.set_shortcut(register, Shortcut::Clear, false);
     }
 
     /// Enables the shortcut between a COMPARE event and the STOP task.
     ///
     /// When the counter reaches the value in the specified CC register, the
     /// counter will be automatically stopped.
+    ///
+    /// The other shortcuts are left unchanged.
     pub fn enable_auto_stop(&mut self, register: TimerCc) {
-        self.timer.shorts.write(|w| match register {
-            TimerCc::CC0 => w.compare0_stop().enabled(),
-            TimerCc::CC1 => w.compare1_stop().enabled(),
-            TimerCc::CC2 => w.compare2_stop().enabled(),
-            TimerCc::CC3 => w.compare3_stop().enabled(),
-        });
+        self.set_shortcut(register, Shortcut::Stop, true);
     }
 
     /// Disables the shortcut between a COMPARE event and the STOP task.
+    ///
+    /// The other shortcuts are left unchanged.
     pub fn disable_auto_stop(&mut self, register: TimerCc) {
-        self.timer.shorts.write(|w| match register {
-            TimerCc::CC0 => w.compare0_stop().disabled(),
-            TimerCc::CC1 => w.compare1_stop().disabled(),
-            TimerCc::CC2 => w.compare2_stop().disabled(),
-            TimerCc::CC3 => w.compare3_stop().disabled(),
-        });
+        self.set_shortcut(register, Shortcut::Stop, false);
+    }
+}
+
+/// The task a COMPARE event can be shortcut to.
+#[derive(Copy, Clone)]
+enum Shortcut {
+    Clear,
+    Stop,
+}
+
+/// Returns the SHORTS register value `shorts` with the shortcut from the
+/// specified CC register's COMPARE event to `task` enabled or disabled.
+fn with_shortcut(shorts: u32, register: TimerCc, task: Shortcut, enabled: bool) -> u32 {
+    // COMPARE[n]_CLEAR is bit n, COMPARE[n]_STOP is bit n + 8
+    let bit = match task {
+        Shortcut::Clear => 1 << register as u32,
+        Shortcut::Stop => 1 << (register as u32 + 8),
+    };
+    if enabled {
+        shorts | bit
+    } else {
+        shorts & !bit
     }
 }
 
@@ -504,3 +725,161 @@ impl Nrf51Timer for nrf51::TIMER2 {
         self.as_16bit_timer()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zeroed memory standing in for a TIMER's registers
+    struct FakeTimer(nrf51::timer0::RegisterBlock);
+
+    impl FakeTimer {
+        fn new() -> FakeTimer {
+            FakeTimer(unsafe { core::mem::zeroed() })
+        }
+    }
+
+    impl Deref for FakeTimer {
+        type Target = nrf51::timer0::RegisterBlock;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Nrf51Timer for FakeTimer {
+        type MaxWidth = u32;
+
+        fn as_max_width_timer(self) -> HiResTimer<Self, u32> {
+            HiResTimer::new(self)
+        }
+    }
+
+    /// Reads back the value last written to a write-only register
+    fn written<R>(reg: &R) -> u32 {
+        unsafe { (reg as *const R as *const u32).read_volatile() }
+    }
+
+    #[test]
+    fn new_resets_a_previously_used_timer() {
+        let fake = FakeTimer::new();
+        fake.mode.write(|w| w.mode().counter());
+        fake.bitmode.write(|w| w.bitmode()._32bit());
+        fake.prescaler.write(|w| unsafe { w.bits(9) });
+        fake.shorts.write(|w| unsafe { w.bits(0xF0F) });
+        for i in 0..4 {
+            fake.cc[i].write(|w| unsafe { w.bits(1000) });
+            fake.events_compare[i].write(|w| unsafe { w.bits(1) });
+        }
+
+        let timer = HiResTimer::<FakeTimer, u16>::new(fake);
+        let regs = &timer.timer;
+        assert_eq!(written(&regs.tasks_stop), 1);
+        assert_eq!(written(&regs.tasks_clear), 1);
+        assert!(regs.mode.read().mode().is_timer());
+        assert!(regs.bitmode.read().bitmode().is_16bit());
+        assert!(matches!(timer.frequency(), TimerFrequency::Freq1MHz));
+        assert_eq!(regs.shorts.read().bits(), 0);
+        for i in 0..4 {
+            assert_eq!(regs.cc[i].read().bits(), 0);
+            assert_eq!(regs.events_compare[i].read().bits(), 0);
+        }
+        // INTENCLR bits 16 ..= 19 disable the COMPARE interrupts
+        assert_eq!(written(&regs.intenclr), 0x000F_0000);
+    }
+
+    #[test]
+    fn set_frequency_stops_and_toggles_the_prescaler() {
+        let mut timer = FakeTimer::new().as_max_width_timer();
+        let frequencies = [
+            (TimerFrequency::Freq16MHz, 0),
+            (TimerFrequency::Freq31250Hz, 9),
+            (TimerFrequency::Freq16MHz, 0),
+            (TimerFrequency::Freq125kHz, 7),
+        ];
+        for (frequency, prescaler) in frequencies.iter() {
+            timer.timer.tasks_stop.write(|w| unsafe { w.bits(0) });
+            timer.set_frequency(*frequency);
+            assert_eq!(written(&timer.timer.tasks_stop), 1);
+            assert_eq!(timer.timer.prescaler.read().bits(), *prescaler);
+            assert_eq!(timer.frequency().as_prescaler(), *prescaler);
+        }
+    }
+
+    #[test]
+    fn with_shortcut_only_changes_the_given_shortcut() {
+        let shorts = with_shortcut(0, TimerCc::CC0, Shortcut::Clear, true);
+        assert_eq!(shorts, 0x0001);
+        let shorts = with_shortcut(shorts, TimerCc::CC1, Shortcut::Stop, true);
+        assert_eq!(shorts, 0x0201);
+        let shorts = with_shortcut(shorts, TimerCc::CC0, Shortcut::Clear, false);
+        assert_eq!(shorts, 0x0200);
+        // Disabling one that isn't enabled changes nothing
+        let shorts = with_shortcut(shorts, TimerCc::CC1, Shortcut::Clear, false);
+        assert_eq!(shorts, 0x0200);
+        let shorts = with_shortcut(shorts, TimerCc::CC3, Shortcut::Stop, true);
+        assert_eq!(shorts, 0x0A00);
+    }
+
+    #[test]
+    fn elapsed_since_clear_uses_the_given_register() {
+        let mut timer = HiResTimer::<FakeTimer, u16>::new(FakeTimer::new());
+        // As if the counter had been captured at 1500 ticks
+        timer.timer.cc[2].write(|w| unsafe { w.bits(1500) });
+        timer.timer.cc[3].write(|w| unsafe { w.bits(7) });
+
+        let elapsed = timer.elapsed_since_clear(TimerCc::CC2).unwrap();
+        assert_eq!(written(&timer.timer.tasks_capture[2]), 1);
+        assert_eq!(written(&timer.timer.tasks_capture[3]), 0);
+        // At the default 1MHz
+        assert_eq!(elapsed, Duration::from_micros(1500));
+    }
+
+    #[test]
+    fn durations_are_rejected_in_counter_mode() {
+        let mut timer = HiResTimer::<FakeTimer, u16>::new(FakeTimer::new());
+        assert!(!timer.is_counter_mode());
+        assert_eq!(timer.max_interval().unwrap(), Duration::from_micros(65536));
+
+        timer.timer.mode.write(|w| w.mode().counter());
+        assert!(timer.is_counter_mode());
+        assert!(timer.max_interval().is_err());
+        assert!(timer.elapsed_since_clear(TimerCc::CC1).is_err());
+    }
+
+    #[test]
+    fn power_up_restores_the_configuration_saved_by_shut_down() {
+        // 8-bit, as 16-bit is BITMODE's reset value
+        let mut timer = HiResTimer::<FakeTimer, u8>::new(FakeTimer::new());
+        timer.set_frequency(TimerFrequency::Freq31250Hz);
+        timer.enable_auto_clear(TimerCc::CC0);
+        timer.set_compare_register(TimerCc::CC0, 200);
+        timer.set_compare_register(TimerCc::CC3, 3);
+
+        timer.shut_down();
+        assert_eq!(written(&timer.timer.tasks_shutdown), 1);
+
+        // Losing power resets the registers
+        let regs = &timer.timer;
+        regs.bitmode.reset();
+        regs.prescaler.reset();
+        regs.shorts.reset();
+        for cc in regs.cc.iter() {
+            cc.reset();
+        }
+
+        timer.power_up();
+        let regs = &timer.timer;
+        assert!(regs.mode.read().mode().is_timer());
+        assert!(regs.bitmode.read().bitmode().is_08bit());
+        assert!(matches!(timer.frequency(), TimerFrequency::Freq31250Hz));
+        assert!(timer.is_auto_clear_enabled(TimerCc::CC0));
+        assert!(!timer.is_auto_clear_enabled(TimerCc::CC1));
+        assert_eq!(timer.captured_all(), [200, 0, 0, 3]);
+
+        // Only the first power_up() restores anything
+        timer.set_frequency(TimerFrequency::Freq1MHz);
+        timer.power_up();
+        assert!(matches!(timer.frequency(), TimerFrequency::Freq1MHz));
+    }
+}
@@ -1,59 +1,136 @@
+use crate::delay::busy_wait_cycles;
 use crate::gpio::gpio::PIN;
-use crate::gpio::{Input, OpenDrain};
+use crate::gpio::{Input, OpenDrain, PeripheralPin};
 use nrf51::twi0::frequency;
-use nrf51::TWI1;
+use nrf51::{twi0, TWI0, TWI1};
 
 use embedded_hal::blocking::i2c::{Write, WriteRead};
 
+use core::ops::Deref;
+
+/// Upper bound on the polls waiting for the stop condition closing a failed
+/// transaction, well beyond the time it takes even at 100kHz
+const MAX_STOP_POLLS: u32 = 10_000;
+
 /// I2C abstraction
-pub struct I2c<I2C> {
+///
+/// `SDA` and `SCL` are the types of the pins, either numbered (e.g.
+/// `PIN30<Input<OpenDrain>>`) or erased.
+pub struct I2c<I2C, SDA = PIN<Input<OpenDrain>>, SCL = PIN<Input<OpenDrain>>> {
     i2c: I2C,
-    sdapin: PIN<Input<OpenDrain>>,
-    sclpin: PIN<Input<OpenDrain>>,
+    sdapin: SDA,
+    sclpin: SCL,
+    retries: u8,
+    retry_delay_us: u32,
 }
 
 #[derive(Debug)]
 pub enum Error {
     OVERRUN,
     NACK,
+    /// The slave didn't acknowledge its address
+    ANACK,
 }
 
 pub enum Frequency {
     K100,
     K250,
     K400,
+    /// A raw FREQUENCY register value, see `Frequency::from_hz`
+    Custom(u32),
 }
 
-impl Into<frequency::FREQUENCY_A> for Frequency {
-    fn into(self) -> frequency::FREQUENCY_A {
-        match self {
-            Frequency::K100 => frequency::FREQUENCY_A::K100,
-            Frequency::K250 => frequency::FREQUENCY_A::K250,
-            Frequency::K400 => frequency::FREQUENCY_A::K400,
+/// Step between the FREQUENCY register values giving distinct rates
+const FREQUENCY_STEP: u64 = 1 << 19;
+
+impl Frequency {
+    /// Returns the frequency closest to `hz`
+    ///
+    /// The FREQUENCY register holds about `hz * 2^32 / 16MHz`, of which only
+    /// the bits from bit 19 up are used, so the achievable rates are spaced
+    /// 16MHz / 2^13, i.e. about 1953Hz, apart. This is how the three standard
+    /// values are derived, and they're returned as `K100`, `K250` and `K400`.
+    /// Nordic only specifies those three; check any other rate against the
+    /// requirements of the bus and its devices, e.g. with a scope.
+    pub fn from_hz(hz: u32) -> Frequency {
+        let exact = (u64::from(hz) << 32) / 16_000_000;
+        let bits = (exact + FREQUENCY_STEP / 2) / FREQUENCY_STEP * FREQUENCY_STEP;
+        let bits = bits.min(u64::from(u32::MAX) & !(FREQUENCY_STEP - 1)) as u32;
+        match bits {
+            0x0198_0000 => Frequency::K100,
+            0x0400_0000 => Frequency::K250,
+            0x0668_0000 => Frequency::K400,
+            _ => Frequency::Custom(bits),
+        }
+    }
+
+    /// Returns the FREQUENCY register value
+    fn bits(&self) -> u32 {
+        match *self {
+            Frequency::K100 => frequency::FREQUENCY_A::K100.into(),
+            Frequency::K250 => frequency::FREQUENCY_A::K250.into(),
+            Frequency::K400 => frequency::FREQUENCY_A::K400.into(),
+            Frequency::Custom(bits) => bits,
         }
     }
 }
 
-impl I2c<TWI1> {
-    pub fn i2c1(i2c: TWI1, sdapin: PIN<Input<OpenDrain>>, sclpin: PIN<Input<OpenDrain>>) -> Self {
+/// One of the nRF51's TWI instances (`nrf51::TWI0` or `nrf51::TWI1`)
+pub trait TwiInstance: Deref<Target = twi0::RegisterBlock> + Sized {}
+
+impl TwiInstance for TWI0 {}
+impl TwiInstance for TWI1 {}
+
+impl<SDA, SCL> I2c<TWI0, SDA, SCL>
+where
+    SDA: PeripheralPin<Input<OpenDrain>>,
+    SCL: PeripheralPin<Input<OpenDrain>>,
+{
+    pub fn i2c0(i2c: TWI0, sdapin: SDA, sclpin: SCL) -> Self {
+        Self::i2c0_with_frequency(i2c, sdapin, sclpin, Frequency::K250)
+    }
+
+    pub fn i2c0_with_frequency(i2c: TWI0, sdapin: SDA, sclpin: SCL, frequency: Frequency) -> Self {
+        Self::new(i2c, sdapin, sclpin, frequency)
+    }
+}
+
+impl<SDA, SCL> I2c<TWI1, SDA, SCL>
+where
+    SDA: PeripheralPin<Input<OpenDrain>>,
+    SCL: PeripheralPin<Input<OpenDrain>>,
+{
+    pub fn i2c1(i2c: TWI1, sdapin: SDA, sclpin: SCL) -> Self {
         Self::i2c1_with_frequency(i2c, sdapin, sclpin, Frequency::K250)
     }
 
-    pub fn i2c1_with_frequency(
-        i2c: TWI1,
-        sdapin: PIN<Input<OpenDrain>>,
-        sclpin: PIN<Input<OpenDrain>>,
-        frequency: Frequency,
-    ) -> Self {
+    pub fn i2c1_with_frequency(i2c: TWI1, sdapin: SDA, sclpin: SCL, frequency: Frequency) -> Self {
+        Self::new(i2c, sdapin, sclpin, frequency)
+    }
+}
+
+impl<TWI, SDA, SCL> I2c<TWI, SDA, SCL>
+where
+    TWI: TwiInstance,
+{
+    /// Interface to a TWI instance
+    ///
+    /// The TWI instances share the same address space with the SPI instances:
+    /// TWI0 conflicts with SPI0 and TWI1 with SPI1. You need to make sure that
+    /// the conflicting instance is disabled before using `I2c`.
+    pub fn new(i2c: TWI, sdapin: SDA, sclpin: SCL, frequency: Frequency) -> Self
+    where
+        SDA: PeripheralPin<Input<OpenDrain>>,
+        SCL: PeripheralPin<Input<OpenDrain>>,
+    {
         /* Tell I2C controller which pins to use for sending and receiving */
         i2c.pselscl
-            .write(|w| unsafe { w.bits(sclpin.get_id().into()) });
+            .write(|w| unsafe { w.bits(sclpin.pin_id().into()) });
         i2c.pselsda
-            .write(|w| unsafe { w.bits(sdapin.get_id().into()) });
+            .write(|w| unsafe { w.bits(sdapin.pin_id().into()) });
 
         /* Set master clock frequency */
-        i2c.frequency
-            .write(|w| w.frequency().variant(frequency.into()));
+        i2c.frequency.write(|w| unsafe { w.bits(frequency.bits()) });
 
         /* Enable i2c function */
         i2c.enable.write(|w| w.enable().enabled());
@@ -62,16 +139,100 @@ impl I2c<TWI1> {
             i2c,
             sdapin,
             sclpin,
+            retries: 0,
+            retry_delay_us: 0,
         }
     }
 
-    pub fn release(self) -> (TWI1, PIN<Input<OpenDrain>>, PIN<Input<OpenDrain>>) {
+    /// Sets how often a transaction is retried if the slave doesn't
+    /// acknowledge its address
+    ///
+    /// Many devices don't acknowledge their address while they are busy, e.g.
+    /// an EEPROM during a write cycle. With `retries` > 0 a transaction
+    /// failing with `Error::ANACK` is repeated, after waiting `delay_us`
+    /// microseconds, up to `retries` times before the error is returned. The
+    /// wait is a busy-wait on the 16MHz core clock, so `delay_us` is a minimum
+    /// and interrupts make it longer. Other errors are returned immediately. By default transactions aren't retried.
+    pub fn set_retries(&mut self, retries: u8, delay_us: u32) {
+        self.retries = retries;
+        self.retry_delay_us = delay_us;
+    }
+
+    /// Runs `transaction`, repeating it as configured by `set_retries` while
+    /// it fails with `Error::ANACK`
+    fn with_retries<F>(&self, mut transaction: F) -> Result<(), Error>
+    where
+        F: FnMut(&Self) -> Result<(), Error>,
+    {
+        let mut retries = self.retries;
+        loop {
+            match transaction(self) {
+                Err(Error::ANACK) if retries > 0 => {
+                    self.release_bus();
+                    busy_wait_cycles(self.retry_delay_us.saturating_mul(16));
+                    retries -= 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Sends a stop condition after a failed transaction unless it already
+    /// ended with one
+    ///
+    /// The wait for the stop is bounded so a stuck bus doesn't hang the
+    /// retries; the next attempt then reports the problem.
+    fn release_bus(&self) {
+        let twi = &self.i2c;
+
+        if twi.events_stopped.read().bits() != 0 {
+            return;
+        }
+
+        twi.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        for _ in 0..MAX_STOP_POLLS {
+            if twi.events_stopped.read().bits() != 0 {
+                break;
+            }
+        }
+
+        /* Don't let an error from the stop leak into the next attempt */
+        if twi.events_error.read().bits() != 0 {
+            self.take_error(Error::NACK);
+        }
+    }
+
+    pub fn release(self) -> (TWI, SDA, SCL) {
         (self.i2c, self.sdapin, self.sclpin)
     }
 
+    /// Clears a pending error, returning `Error::ANACK` if the address wasn't
+    /// acknowledged and `default` otherwise
+    fn take_error(&self, default: Error) -> Error {
+        let twi = &self.i2c;
+
+        /* Clear error event */
+        twi.events_error.write(|w| unsafe { w.bits(0) });
+
+        /* Error source bits are cleared by writing 1 to them */
+        let errorsrc = twi.errorsrc.read();
+        twi.errorsrc.write(|w| unsafe { w.bits(errorsrc.bits()) });
+
+        if errorsrc.anack().is_present() {
+            Error::ANACK
+        } else {
+            default
+        }
+    }
+
     fn send_start(&self) -> Result<(), Error> {
         let twi = &self.i2c;
 
+        /* Clear stopped event so a failed transaction can tell whether it
+         * already ended with a stop condition */
+        twi.events_stopped.write(|w| unsafe { w.bits(0) });
+
         /* Start data transmission */
         twi.tasks_starttx.write(|w| unsafe { w.bits(1) });
         Ok(())
@@ -90,8 +251,7 @@ impl I2c<TWI1> {
         while twi.events_txdsent.read().bits() == 0 {
             /* Bail out if we get an error instead */
             if twi.events_error.read().bits() != 0 {
-                twi.events_error.write(|w| unsafe { w.bits(0) });
-                return Err(Error::NACK);
+                return Err(self.take_error(Error::NACK));
             }
         }
 
@@ -108,8 +268,7 @@ impl I2c<TWI1> {
         while twi.events_rxdready.read().bits() == 0 {
             /* Bail out if it's an error instead of data */
             if twi.events_error.read().bits() != 0 {
-                twi.events_error.write(|w| unsafe { w.bits(0) });
-                return Err(Error::OVERRUN);
+                return Err(self.take_error(Error::OVERRUN));
             }
         }
 
@@ -122,40 +281,46 @@ impl I2c<TWI1> {
         Ok(out)
     }
 
-    fn send_stop(&self) -> Result<(), Error> {
-        let twi = &self.i2c;
-
-        /* Clear stopped event */
-        twi.events_stopped.write(|w| unsafe { w.bits(0) });
-
-        /* Start stop condition */
-        twi.tasks_stop.write(|w| unsafe { w.bits(1) });
+    /// Prepares for a series of transactions with the slave at `addr`
+    ///
+    /// The returned handle sets the slave address once and remembers the
+    /// shortcut configuration left by the previous transaction, so it only
+    /// writes these registers when they actually need to change. This saves a
+    /// few bus writes per transaction when polling one device at a high rate.
+    pub fn prepare(&mut self, addr: u8) -> Prepared<'_, TWI, SDA, SCL> {
+        self.i2c
+            .address
+            .write(|w| unsafe { w.address().bits(addr) });
+        Prepared {
+            i2c: self,
+            shorts: None,
+        }
+    }
 
-        /* Wait until stop was sent */
-        while twi.events_stopped.read().bits() == 0 {
-            /* Bail out if we get an error instead */
-            if twi.events_error.read().bits() != 0 {
-                twi.events_error.write(|w| unsafe { w.bits(0) });
-                return Err(Error::NACK);
-            }
+    /// Configures the shortcuts, unless `current` says they already are
+    fn set_shorts(&self, current: &mut Option<Shorts>, shorts: Shorts) {
+        if *current == Some(shorts) {
+            return;
         }
 
-        Ok(())
+        self.i2c.shorts.write(|w| match shorts {
+            Shorts::None => w.bb_stop().disabled().bb_suspend().disabled(),
+            Shorts::Suspend => w.bb_suspend().enabled(),
+            Shorts::Stop => w.bb_stop().enabled(),
+        });
+        *current = Some(shorts);
     }
-}
-
-impl WriteRead for I2c<TWI1> {
-    type Error = Error;
 
-    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+    fn do_write_read(
+        &self,
+        shorts: &mut Option<Shorts>,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
         let twi = &self.i2c;
 
         /* Make sure all previously used shortcuts are disabled */
-        twi.shorts
-            .write(|w| w.bb_stop().disabled().bb_suspend().disabled());
-
-        /* Request data */
-        twi.address.write(|w| unsafe { w.address().bits(addr) });
+        self.set_shorts(shorts, Shorts::None);
 
         self.send_start()?;
 
@@ -165,51 +330,49 @@ impl WriteRead for I2c<TWI1> {
         }
 
         /* Turn around to read data */
-        if let Some((last, before)) = buffer.split_last_mut() {
-            /* If we want to read multiple bytes we need to use the suspend mode */
-            if !before.is_empty() {
-                twi.shorts.write(|w| w.bb_suspend().enabled());
-            } else {
-                twi.shorts.write(|w| w.bb_stop().enabled());
-            }
+        let len = buffer.len();
+        let mut reads = buffer.iter_mut().zip(read_shorts(len)).peekable();
+        if let Some(first) = reads.peek().map(|(_, s)| *s) {
+            self.set_shorts(shorts, first);
 
-            /* Clear reception event */
+            /* Clear reception and stopped events */
             twi.events_rxdready.write(|w| unsafe { w.bits(0) });
+            twi.events_stopped.write(|w| unsafe { w.bits(0) });
 
             /* Start data reception */
             twi.tasks_startrx.write(|w| unsafe { w.bits(1) });
 
-            for in_ in &mut before.into_iter() {
-                twi.tasks_resume.write(|w| unsafe { w.bits(1) });
+            while let Some((in_, _)) = reads.next() {
                 *in_ = self.recv_byte()?;
+
+                /* If more bytes follow the TWI is suspended now; arm the
+                 * shortcut for the next byte before resuming */
+                if let Some(next) = reads.peek().map(|(_, s)| *s) {
+                    self.set_shorts(shorts, next);
+                    twi.tasks_resume.write(|w| unsafe { w.bits(1) });
+                }
             }
 
-            twi.shorts.write(|w| w.bb_stop().enabled());
-            twi.tasks_resume.write(|w| unsafe { w.bits(1) });
-            *last = self.recv_byte()?;
+            self.wait_stopped()?;
         } else {
             self.send_stop()?;
         }
         Ok(())
     }
-}
-
-impl Write for I2c<TWI1> {
-    type Error = Error;
-
-    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
-        let twi = &self.i2c;
 
+    fn do_write(&self, shorts: &mut Option<Shorts>, bytes: &[u8]) -> Result<(), Error> {
         /* Make sure all previously used shortcuts are disabled */
-        twi.shorts
-            .write(|w| w.bb_stop().disabled().bb_suspend().disabled());
-
-        /* Set Slave I2C address */
-        twi.address.write(|w| unsafe { w.address().bits(addr) });
+        self.set_shorts(shorts, Shorts::None);
 
         /* Send start condition */
         self.send_start()?;
 
+        /* Without any data this is an address-only probe: the stop condition
+         * follows the address and an address NACK is reported as an error */
+        if bytes.is_empty() {
+            return self.send_stop();
+        }
+
         /* Clock out all bytes */
         for in_ in bytes {
             self.send_byte(*in_)?;
@@ -219,4 +382,164 @@ impl Write for I2c<TWI1> {
         self.send_stop()?;
         Ok(())
     }
+
+    fn send_stop(&self) -> Result<(), Error> {
+        let twi = &self.i2c;
+
+        /* Clear stopped event */
+        twi.events_stopped.write(|w| unsafe { w.bits(0) });
+
+        /* Start stop condition */
+        twi.tasks_stop.write(|w| unsafe { w.bits(1) });
+
+        self.wait_stopped()
+    }
+
+    /// Waits until the stop condition was sent, returning any error raised
+    /// before or along with it
+    fn wait_stopped(&self) -> Result<(), Error> {
+        let twi = &self.i2c;
+
+        while twi.events_stopped.read().bits() == 0 {
+            /* Bail out if we get an error instead */
+            if twi.events_error.read().bits() != 0 {
+                return Err(self.take_error(Error::NACK));
+            }
+        }
+
+        /* The error and the stop can both happen before the first poll, e.g.
+         * when an interrupt delays it, so check for an error once more */
+        if twi.events_error.read().bits() != 0 {
+            return Err(self.take_error(Error::NACK));
+        }
+
+        Ok(())
+    }
+}
+
+impl<TWI, SDA, SCL> WriteRead for I2c<TWI, SDA, SCL>
+where
+    TWI: TwiInstance,
+{
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        /* Request data */
+        self.i2c
+            .address
+            .write(|w| unsafe { w.address().bits(addr) });
+
+        self.with_retries(|i2c| i2c.do_write_read(&mut None, bytes, buffer))
+    }
+}
+
+impl<TWI, SDA, SCL> Write for I2c<TWI, SDA, SCL>
+where
+    TWI: TwiInstance,
+{
+    type Error = Error;
+
+    /// Writes `bytes` to the slave at `addr`
+    ///
+    /// An empty `bytes` slice probes for the slave: `Ok` is returned if it
+    /// acknowledges its address and `Err(Error::ANACK)` if it doesn't.
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        /* Set Slave I2C address */
+        self.i2c
+            .address
+            .write(|w| unsafe { w.address().bits(addr) });
+
+        self.with_retries(|i2c| i2c.do_write(&mut None, bytes))
+    }
+}
+
+/// Shortcut configuration used by the transactions
+#[derive(Clone, Copy, PartialEq)]
+enum Shorts {
+    None,
+    Suspend,
+    Stop,
+}
+
+/// Returns the shortcut to arm while receiving each byte of a `len` byte read
+///
+/// The TWI suspends after every byte but the last so the next shortcut can be
+/// armed before resuming. The last byte is received with the STOP shortcut,
+/// so it is NACKed and followed by the stop condition.
+fn read_shorts(len: usize) -> impl Iterator<Item = Shorts> {
+    (0..len).map(move |i| {
+        if i + 1 < len {
+            Shorts::Suspend
+        } else {
+            Shorts::Stop
+        }
+    })
+}
+
+/// `I2c` prepared for repeated transactions with one slave
+///
+/// Created by `I2c::prepare`. The transactions behave like the ones of the
+/// `Write` and `WriteRead` implementations, without the address argument.
+pub struct Prepared<'a, TWI, SDA = PIN<Input<OpenDrain>>, SCL = PIN<Input<OpenDrain>>> {
+    i2c: &'a mut I2c<TWI, SDA, SCL>,
+    /* Shortcuts as configured by the last transaction, None if unknown */
+    shorts: Option<Shorts>,
+}
+
+impl<'a, TWI, SDA, SCL> Prepared<'a, TWI, SDA, SCL>
+where
+    TWI: TwiInstance,
+{
+    /// Writes `bytes` to the slave, then reads enough bytes to fill `buffer`
+    pub fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        let shorts = &mut self.shorts;
+        self.i2c
+            .with_retries(|i2c| i2c.do_write_read(shorts, bytes, buffer))
+    }
+
+    /// Writes `bytes` to the slave
+    ///
+    /// An empty `bytes` slice probes for the slave, see `I2c::write`.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let shorts = &mut self.shorts;
+        self.i2c.with_retries(|i2c| i2c.do_write(shorts, bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hz_returns_the_standard_frequencies() {
+        assert!(matches!(Frequency::from_hz(100_000), Frequency::K100));
+        assert!(matches!(Frequency::from_hz(250_000), Frequency::K250));
+        assert!(matches!(Frequency::from_hz(400_000), Frequency::K400));
+        /* Within half a step of 400kHz */
+        assert!(matches!(Frequency::from_hz(400_900), Frequency::K400));
+    }
+
+    #[test]
+    fn from_hz_rounds_to_the_register_step() {
+        /* 102.4 steps */
+        assert_eq!(Frequency::from_hz(200_000).bits(), 0x0330_0000);
+        /* 166.6 steps */
+        assert_eq!(Frequency::from_hz(325_500).bits(), 0x0538_0000);
+        assert_eq!(Frequency::from_hz(0).bits(), 0);
+    }
+
+    #[test]
+    fn from_hz_saturates() {
+        assert_eq!(Frequency::from_hz(u32::MAX).bits(), 0xFFF8_0000);
+    }
+
+    #[test]
+    fn read_shorts_arms_stop_for_the_last_byte_only() {
+        use Shorts::{Stop, Suspend};
+
+        assert_eq!(read_shorts(0).count(), 0);
+        assert!(read_shorts(1).eq([Stop].iter().copied()));
+        assert!(read_shorts(2).eq([Suspend, Stop].iter().copied()));
+        assert!(read_shorts(3).eq([Suspend, Suspend, Stop].iter().copied()));
+    }
 }
@@ -7,10 +7,13 @@ pub mod adc;
 pub mod delay;
 pub mod ecb;
 pub mod gpio;
+#[cfg(feature = "async")]
+pub mod gpiote;
 pub mod hi_res_timer;
 pub mod i2c;
 pub mod lo_res_timer;
 pub mod prelude;
+pub mod pwm;
 pub mod rng;
 pub mod serial;
 pub mod spi;
@@ -13,11 +13,14 @@
 //! ```
 
 use core::ops::Deref;
+use core::time::Duration;
 
 use cast::u32;
 
 use nrf51;
 
+use crate::time::Lfticks;
+
 /// The base frequency of a RTC peripheral, in hertz.
 ///
 /// This is the highest available frequency.
@@ -137,8 +140,68 @@ impl RtcFrequency {
     pub fn scale(self, base_ticks: u64) -> Option<u32> {
         u32(base_ticks / (self.0 + 1) as u64).ok()
     }
+
+    /// Apply the effective prescaler value, keeping the remainder.
+    ///
+    /// Like `scale()`, but also returns the number of base 32768Hz clock
+    /// ticks left over by rounding down. Adding the remainder to the next
+    /// interval before scaling it keeps a series of intervals from drifting:
+    /// the total error stays below one RTC tick however many are chained.
+    ///
+    /// The remainder is returned even if the scaled value doesn't fit in a
+    /// u32.
+    pub fn scale_with_remainder(self, base_ticks: u64) -> (Option<u32>, u64) {
+        let divisor = (self.0 + 1) as u64;
+        (self.scale(base_ticks), base_ticks % divisor)
+    }
+
+    /// Returns the duration of a base 32768Hz clock tick count as a
+    /// `Duration`, rounded down to the nanosecond.
+    fn base_ticks_to_duration(base_ticks: u64) -> Duration {
+        Lfticks(base_ticks).to_duration()
+    }
+
+    /// Returns the period of one RTC tick at this frequency.
+    ///
+    /// Rounded down to the nanosecond.
+    pub fn tick_duration(self) -> Duration {
+        Self::base_ticks_to_duration(self.0 as u64 + 1)
+    }
+
+    /// Returns the longest interval that can be stored in a CC register at
+    /// this frequency (2^24 - 1 ticks).
+    ///
+    /// Rounded down to the nanosecond.
+    pub fn max_interval(self) -> Duration {
+        Self::base_ticks_to_duration(((1 << 24) - 1) * (self.0 as u64 + 1))
+    }
+
+    /// Returns the finest frequency whose `max_interval()` covers
+    /// `max_interval`.
+    ///
+    /// Only the power-of-two prescalers are considered, that is the
+    /// `FREQ_`*nnn*`HZ` constants (a prescaler of 2^n - 1 for n in 0 ..= 12).
+    /// Going down from `FREQ_32768HZ`, the first one covering the interval is
+    /// returned, so the resolution is the best available for it.
+    ///
+    /// Returns `IntervalTooLong` if `max_interval` is longer than even
+    /// `FREQ_8HZ` can cover (over 24 days).
+    pub fn finest_covering(max_interval: Duration) -> Result<RtcFrequency, IntervalTooLong> {
+        for shift in 0..=12 {
+            let frequency = RtcFrequency((1 << shift) - 1);
+            if frequency.max_interval() >= max_interval {
+                return Ok(frequency);
+            }
+        }
+        Err(IntervalTooLong)
+    }
 }
 
+/// Error returned by `RtcFrequency::finest_covering()` when no frequency covers
+/// the requested interval.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IntervalTooLong;
+
 /// RTC frequency 32768Hz
 pub const FREQ_32768HZ: RtcFrequency = RtcFrequency::const_from_prescaler(0);
 
@@ -272,6 +335,25 @@ impl<T: Nrf51Rtc> LoResTimer<T> {
         l
     }
 
+    /// Takes ownership of the RTC peripheral, returning a safe wrapper running
+    /// at the specified frequency.
+    ///
+    /// Does the same as `new()`, then writes the prescaler. Provided the RTC
+    /// hasn't been started since reset, the prescaler is guaranteed to change
+    /// only while the RTC is stopped, as required. This is the safe way to set
+    /// a frequency that doesn't change afterwards.
+    ///
+    /// `set_frequency()` on the other hand stops the RTC before writing the
+    /// prescaler, but the STOP task takes effect with a delay of up to one
+    /// LFCLK cycle, during which the write may be lost.
+    pub fn with_frequency(rtc: T, frequency: RtcFrequency) -> LoResTimer<T> {
+        let l = LoResTimer::new(rtc);
+        l.rtc
+            .prescaler
+            .write(|w| unsafe { w.bits(frequency.as_prescaler()) });
+        l
+    }
+
     /// Stops the RTC and returns the underlying `nrf51::RTC`*n* instance.
     ///
     /// Other than being stopped, the RTC is left in an unspecified state.
@@ -287,7 +369,10 @@ impl<T: Nrf51Rtc> LoResTimer<T> {
 
     /// Stops the RTC and sets its frequency.
     ///
-    /// Writes the prescaler register.
+    /// Writes the prescaler register. The STOP task takes effect with a delay
+    /// of up to one LFCLK cycle, and the prescaler must only be changed while
+    /// the RTC is stopped, so the change may not take effect if the RTC was
+    /// running. Use `with_frequency()` to set the frequency once at startup.
     pub fn set_frequency(&mut self, frequency: RtcFrequency) {
         self.stop();
         self.rtc
@@ -295,11 +380,36 @@ impl<T: Nrf51Rtc> LoResTimer<T> {
             .write(|w| unsafe { w.bits(frequency.as_prescaler()) });
     }
 
+    /// Returns the period of one tick at the RTC's current frequency.
+    pub fn tick_duration(&self) -> Duration {
+        self.frequency().tick_duration()
+    }
+
+    /// Returns the longest interval that can be set with
+    /// `set_compare_register()` at the RTC's current frequency.
+    pub fn max_interval(&self) -> Duration {
+        self.frequency().max_interval()
+    }
+
     /// Returns the RTC's current counter value.
     pub fn read_counter(&self) -> u32 {
         self.rtc.counter.read().counter().bits()
     }
 
+    /// Returns the RTC's current counter value, read twice to make sure it
+    /// was stable.
+    ///
+    /// The counter is read until two consecutive reads agree, so a value
+    /// caught while the counter was being updated is never returned. The RTC
+    /// counts at most at 32.768kHz, so this normally takes two reads.
+    ///
+    /// Note that the START task takes effect with a delay of up to one LFCLK
+    /// cycle (about 30µs), so right after `start()` (or `rearm()`) the counter
+    /// may still hold its old value for a short while.
+    pub fn read_counter_stable(&self) -> u32 {
+        read_until_stable(|| self.read_counter())
+    }
+
     /// Stores a value in the specified CC register.
     ///
     /// # Panics
@@ -328,6 +438,32 @@ impl<T: Nrf51Rtc> LoResTimer<T> {
         self.rtc.tasks_stop.write(|w| unsafe { w.bits(1) });
     }
 
+    /// Restarts the interval set in CC0 from zero.
+    ///
+    /// Stops the RTC, resets the counter, clears any pending COMPARE event
+    /// for CC0 and starts the RTC again. Unlike calling `clear()` alone, this
+    /// makes sure an event which fired just before the call can't be seen
+    /// afterwards, so `poll_compare_event(RtcCc::CC0)` next returns true only
+    /// once the full interval has passed. This is useful for implementing a
+    /// software watchdog.
+    ///
+    /// The CLEAR task takes effect with a delay of up to one LFCLK cycle, so
+    /// the event is cleared after the counter has been reset. That needs the
+    /// LFCLK to be running; the wait for the reset is bounded (to several
+    /// LFCLK cycles), so without it the event is cleared anyway but the
+    /// counter may not have been reset.
+    pub fn rearm(&mut self) {
+        self.stop();
+        self.clear();
+        for _ in 0..MAX_CLEAR_POLLS {
+            if self.read_counter() == 0 {
+                break;
+            }
+        }
+        self.clear_compare_event(RtcCc::CC0);
+        self.start();
+    }
+
     /// Sets the RTC's counter value to 0xFFFFF0.
     ///
     /// This will cause it to overflow shortly afterwards.
@@ -522,6 +658,26 @@ impl<T: Nrf51Rtc> LoResTimer<T> {
     }
 }
 
+/// Upper bound on the polls `LoResTimer::rearm()` waits for the counter to be
+/// reset.
+///
+/// A poll takes at least 4 cycles of the 16MHz core clock, so this allows over
+/// 250µs, more than 8 LFCLK cycles of 30.5µs.
+const MAX_CLEAR_POLLS: u32 = 1_000;
+
+/// Calls `read` until two consecutive calls return the same value, and
+/// returns that value.
+fn read_until_stable<F: FnMut() -> u32>(mut read: F) -> u32 {
+    let mut previous = read();
+    loop {
+        let current = read();
+        if current == previous {
+            return current;
+        }
+        previous = current;
+    }
+}
+
 impl Nrf51Rtc for nrf51::RTC0 {
     fn has_register(register: RtcCc) -> bool {
         register != RtcCc::CC3
@@ -534,3 +690,100 @@ impl Nrf51Rtc for nrf51::RTC1 {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zeroed memory standing in for an RTC's registers
+    struct FakeRtc(nrf51::rtc0::RegisterBlock);
+
+    impl Deref for FakeRtc {
+        type Target = nrf51::rtc0::RegisterBlock;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Nrf51Rtc for FakeRtc {
+        fn has_register(_register: RtcCc) -> bool {
+            true
+        }
+    }
+
+    /// Reads back the value last written to a write-only register
+    fn written<R>(reg: &R) -> u32 {
+        unsafe { (reg as *const R as *const u32).read_volatile() }
+    }
+
+    #[test]
+    fn scale_with_remainder_returns_the_rounding_loss() {
+        // 32 base ticks per tick at 1024Hz
+        assert_eq!(FREQ_1024HZ.scale_with_remainder(100), (Some(3), 4));
+        assert_eq!(FREQ_1024HZ.scale_with_remainder(96), (Some(3), 0));
+        assert_eq!(FREQ_32768HZ.scale_with_remainder(100), (Some(100), 0));
+        assert_eq!(
+            RtcFrequency::from_prescaler(2_u32).scale_with_remainder(10),
+            (Some(3), 1)
+        );
+    }
+
+    #[test]
+    fn scale_with_remainder_keeps_the_remainder_on_overflow() {
+        let base_ticks = (u64::from(u32::MAX) + 1) * 32 + 5;
+        assert_eq!(FREQ_1024HZ.scale_with_remainder(base_ticks), (None, 5));
+    }
+
+    #[test]
+    fn carrying_the_remainder_doesnt_drift() {
+        // Each interval is 1 tick and 1 base tick at 1024Hz
+        let interval = 33;
+        let mut remainder = 0;
+        let mut total = 0;
+        for _ in 0..1000 {
+            let (ticks, rest) = FREQ_1024HZ.scale_with_remainder(interval + remainder);
+            total += u64::from(ticks.unwrap());
+            remainder = rest;
+        }
+        assert_eq!(total, 1000 * interval / 32);
+        assert!(remainder < 32);
+    }
+
+    #[test]
+    fn read_until_stable_waits_for_two_equal_reads() {
+        let reads = [7, 8, 9, 9, 10];
+        let mut calls = 0;
+        let value = read_until_stable(|| {
+            calls += 1;
+            reads[calls - 1]
+        });
+        assert_eq!(value, 9);
+        assert_eq!(calls, 4);
+
+        let mut calls = 0;
+        assert_eq!(
+            read_until_stable(|| {
+                calls += 1;
+                5
+            }),
+            5
+        );
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn rearm_clears_a_stale_compare_event() {
+        let mut rtc = LoResTimer::new(FakeRtc(unsafe { core::mem::zeroed() }));
+        // An event that fired just before, with the counter not (yet) reset,
+        // as if the LFCLK weren't running
+        rtc.rtc.events_compare[0].write(|w| unsafe { w.bits(1) });
+        let counter = &rtc.rtc.counter as *const _ as *mut u32;
+        unsafe { counter.write_volatile(5) };
+
+        rtc.rearm();
+        assert!(!rtc.poll_compare_event(RtcCc::CC0));
+        assert_eq!(written(&rtc.rtc.tasks_clear), 1);
+        assert_eq!(written(&rtc.rtc.tasks_start), 1);
+    }
+}
@@ -0,0 +1,126 @@
+//! Implementation of the embedded-hal `PwmPin` trait on top of a TIMER.
+
+use embedded_hal::PwmPin;
+
+use crate::hi_res_timer::{HiResTimer, Nrf51Timer, TimerCc, TimerFrequency, TimerWidth};
+
+/// A TIMER peripheral as a PWM signal generator.
+///
+/// CC0 holds the period: its COMPARE event is shortcut to the CLEAR task, so
+/// the counter restarts at zero every period. CC1 holds the duty cycle.
+///
+/// `Pwm` only manages the compare registers. Producing a signal on a pin is
+/// left to GPIOTE and PPI: connect a GPIOTE task toggling (or setting and
+/// clearing) the pin to the events at `period_event_address()` and
+/// `duty_event_address()`.
+///
+/// `Pwm` instances implement the embedded-hal `PwmPin` trait, with the duty
+/// cycle expressed in timer ticks, `0 ..= get_max_duty()`. The two ends of
+/// that range need the pin to be driven directly; see `set_duty()`.
+///
+/// # Example
+///
+/// ```ignore
+/// use embedded_hal::PwmPin;
+/// use nrf51_hal::hi_res_timer::TimerFrequency;
+/// use nrf51_hal::pwm::Pwm;
+/// let p = nrf51::Peripherals::take().unwrap();
+/// // 20ms period, as used for servos
+/// let mut pwm = Pwm::new(p.TIMER1, TimerFrequency::Freq1MHz, 20_000);
+/// pwm.set_duty(1_500);
+/// pwm.enable();
+/// ```
+pub struct Pwm<T: Nrf51Timer> {
+    timer: HiResTimer<T, T::MaxWidth>,
+    period: u32,
+    duty: u32,
+}
+
+impl<T: Nrf51Timer> Pwm<T> {
+    /// Returns a new `Pwm` wrapping the passed TIMER.
+    ///
+    /// Takes ownership of the TIMER peripheral.
+    ///
+    /// The TIMER is set to the greatest bit-width it supports and the
+    /// specified frequency. `period` is the length of a PWM period in ticks
+    /// at that frequency. The duty cycle starts at zero and the TIMER is left
+    /// stopped until `enable()` is called.
+    pub fn new(timer: T, frequency: TimerFrequency, period: T::MaxWidth) -> Pwm<T> {
+        let period = period.into();
+        let mut hi_res_timer = timer.as_max_width_timer();
+        hi_res_timer.set_frequency(frequency);
+        hi_res_timer.set_compare_register(
+            TimerCc::CC0,
+            T::MaxWidth::try_from_u32(period).expect("TIMER compare value too wide"),
+        );
+        hi_res_timer.set_compare_register(
+            TimerCc::CC1,
+            T::MaxWidth::try_from_u32(0).expect("TIMER compare value too wide"),
+        );
+        hi_res_timer.enable_auto_clear(TimerCc::CC0);
+        Pwm {
+            timer: hi_res_timer,
+            period,
+            duty: 0,
+        }
+    }
+
+    /// Gives the underlying `nrf51::TIMER`*n* instance back.
+    pub fn free(self) -> T {
+        self.timer.free()
+    }
+
+    /// Returns the address of the COMPARE event marking the end of each
+    /// period (CC0), for use as a PPI event end point.
+    pub fn period_event_address(&self) -> u32 {
+        self.timer.compare_event_address(TimerCc::CC0)
+    }
+
+    /// Returns the address of the COMPARE event marking the end of the duty
+    /// part of each period (CC1), for use as a PPI event end point.
+    pub fn duty_event_address(&self) -> u32 {
+        self.timer.compare_event_address(TimerCc::CC1)
+    }
+}
+
+impl<T: Nrf51Timer> PwmPin for Pwm<T> {
+    type Duty = u32;
+
+    /// Stops the TIMER.
+    fn disable(&mut self) {
+        self.timer.stop();
+    }
+
+    /// Restarts the TIMER from the beginning of a period.
+    fn enable(&mut self) {
+        self.timer.clear();
+        self.timer.start();
+    }
+
+    fn get_duty(&self) -> u32 {
+        self.duty
+    }
+
+    /// Returns the period, in ticks.
+    fn get_max_duty(&self) -> u32 {
+        self.period
+    }
+
+    /// Sets the duty cycle, in ticks.
+    ///
+    /// Values greater than `get_max_duty()` are clamped.
+    ///
+    /// A duty cycle of 0 or of `get_max_duty()` doesn't give a steady level:
+    /// CC1 then matches at the same counter value as CC0, or right after
+    /// it, so the CC0 and CC1 COMPARE events come together once per period.
+    /// A GPIOTE toggle connected to both then toggles twice in a row (or the
+    /// two tasks collide), giving a glitch every period rather than 0% or
+    /// 100%. `Pwm` doesn't own the pin, so it can't hold it low or high
+    /// itself. For these two values, disconnect the GPIOTE task and drive
+    /// the pin as a plain output instead.
+    fn set_duty(&mut self, duty: u32) {
+        self.duty = duty.min(self.period);
+        let ticks = T::MaxWidth::try_from_u32(self.duty).expect("TIMER compare value too wide");
+        self.timer.set_compare_register(TimerCc::CC1, ticks);
+    }
+}
@@ -1,3 +1,6 @@
+use core::mem::ManuallyDrop;
+use core::ptr;
+
 use nrf51::RNG;
 
 /// Blocking read
@@ -12,24 +15,176 @@ pub trait Read {
 #[derive(Debug)]
 pub enum Error {}
 
+/// Failure of the random number generator health test
+#[derive(Debug)]
+pub enum RngHealthError {
+    /// The same value was produced several times in a row
+    Repetition,
+    /// The proportion of set bits was far from one half
+    Monobit,
+}
+
+/// Number of bytes sampled by `Rng::self_test`
+const HEALTH_TEST_BYTES: usize = 64;
+
+/// Number of identical consecutive bytes making the repetition test fail
+const HEALTH_TEST_MAX_REPEAT: usize = 4;
+
+/// Accepted range of set bits in the sampled bytes (roughly five standard
+/// deviations around the expected 256 out of 512)
+const HEALTH_TEST_ONES: core::ops::RangeInclusive<u32> = 200..=312;
+
 /// System random number generator `RNG` as a random number provider
+///
+/// The generator runs, drawing current, from `new()` until the `Rng` is
+/// freed or dropped; both stop it.
 pub struct Rng {
     rng: RNG,
+    /* Bits of the latest value not yet returned by read_bit(), LSB first */
+    bits: u8,
+    bits_left: u8,
 }
 
 impl Rng {
+    /// Starts the random number generator
+    ///
+    /// Any value latched from a previous session (e.g. before `free()`) is
+    /// discarded, so the first byte read is always freshly generated.
     pub fn new(rng: RNG) -> Self {
         /* Enable error correction for better values */
         rng.config.write(|w| w.dercen().enabled());
 
+        /* Drop any value left over from a previous session */
+        rng.events_valrdy.write(|w| unsafe { w.bits(0) });
+
         /* Enable random number generation */
         rng.tasks_start.write(|w| unsafe { w.bits(1) });
 
-        Rng { rng }
+        /* Discard the first value, which may have been latched before */
+        let mut rng = Rng {
+            rng,
+            bits: 0,
+            bits_left: 0,
+        };
+        rng.read_byte();
+
+        rng
     }
 
+    /// Stops the random number generator and returns the peripheral
     pub fn free(self) -> RNG {
-        self.rng
+        self.stop();
+
+        /* Move the peripheral out without running drop() */
+        let this = ManuallyDrop::new(self);
+        unsafe { ptr::read(&this.rng) }
+    }
+
+    fn stop(&self) {
+        self.rng.tasks_stop.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Performs a basic health test of the generator
+    ///
+    /// Reads 64 bytes and checks them with a repetition count test (failing
+    /// if a value repeats 4 times in a row) and a monobit test (failing if
+    /// fewer than 200 or more than 312 of the 512 bits are set). This is a
+    /// lightweight take on the NIST SP 800-90B start-up tests, meant to catch
+    /// a stuck generator before its output is used, e.g. to seed crypto. A
+    /// healthy generator fails it with a probability of less than 10^-5.
+    ///
+    /// The sampled bytes are discarded.
+    pub fn self_test(&mut self) -> Result<(), RngHealthError> {
+        let mut buffer = [0u8; HEALTH_TEST_BYTES];
+        for in_ in buffer.iter_mut() {
+            *in_ = self.read_byte();
+        }
+
+        let mut repeats = 1;
+        for pair in buffer.windows(2) {
+            if pair[0] == pair[1] {
+                repeats += 1;
+                if repeats >= HEALTH_TEST_MAX_REPEAT {
+                    return Err(RngHealthError::Repetition);
+                }
+            } else {
+                repeats = 1;
+            }
+        }
+
+        let ones: u32 = buffer.iter().map(|b| b.count_ones()).sum();
+        if !HEALTH_TEST_ONES.contains(&ones) {
+            return Err(RngHealthError::Monobit);
+        }
+
+        Ok(())
+    }
+
+    /// Reads random bytes into `buffer` for at most `max_iters` polls of the
+    /// generator, returning how many bytes were read
+    ///
+    /// Each check for a new value counts as one iteration, whether or not the
+    /// value is ready, so the time spent is bounded even if the generator is
+    /// slow. The bytes read are at the start of `buffer`; the rest is left
+    /// untouched. Returns `buffer.len()` if it could be filled in time.
+    pub fn read_available(&mut self, buffer: &mut [u8], max_iters: u32) -> usize {
+        let mut count = 0;
+        for _ in 0..max_iters {
+            if count == buffer.len() {
+                break;
+            }
+            if let Some(byte) = self.try_read_byte() {
+                buffer[count] = byte;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Returns one random bit
+    ///
+    /// Each generated byte is handed out one bit at a time, least
+    /// significant first, so reading single bits uses the generator's output
+    /// no faster than needed. Returns `WouldBlock` if all bits of the last
+    /// byte have been used and the next one isn't ready yet.
+    ///
+    /// The buffered bits are separate from the byte API: reading bytes
+    /// neither uses nor discards them.
+    pub fn read_bit(&mut self) -> nb::Result<bool, Error> {
+        if self.bits_left == 0 {
+            self.bits = self.try_read_byte().ok_or(nb::Error::WouldBlock)?;
+            self.bits_left = 8;
+        }
+
+        let bit = self.bits & 1 != 0;
+        self.bits >>= 1;
+        self.bits_left -= 1;
+        Ok(bit)
+    }
+
+    fn try_read_byte(&mut self) -> Option<u8> {
+        if self.rng.events_valrdy.read().bits() == 0 {
+            None
+        } else {
+            Some(self.take_byte())
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        /* Let's wait until we have a new random value */
+        while self.rng.events_valrdy.read().bits() == 0 {}
+
+        self.take_byte()
+    }
+
+    fn take_byte(&mut self) -> u8 {
+        /* Fetch random number */
+        let byte = self.rng.value.read().bits() as u8;
+
+        /* Clear event for next random number value */
+        self.rng.events_valrdy.write(|w| unsafe { w.bits(0) });
+
+        byte
     }
 }
 
@@ -38,16 +193,17 @@ impl Read for Rng {
 
     fn read(&mut self, buffer: &mut [u8]) -> Result<(), Self::Error> {
         for in_ in &mut buffer.into_iter() {
-            /* Let's wait until we have a new random value */
-            while self.rng.events_valrdy.read().bits() == 0 {}
-
             /* Write fetched random number into provided buffer */
-            *in_ = self.rng.value.read().bits() as u8;
-
-            /* Clear event for next random number value */
-            self.rng.events_valrdy.write(|w| unsafe { w.bits(0) });
+            *in_ = self.read_byte();
         }
 
         Ok(())
     }
 }
+
+impl Drop for Rng {
+    /// Stops the random number generator
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -3,23 +3,143 @@ use core::marker::PhantomData;
 
 use nb::block;
 
+use crate::delay::busy_wait_cycles;
 use crate::gpio::gpio::PIN;
-use crate::gpio::{Floating, Input, Output, PushPull};
-use nrf51::UART0;
-use void::Void;
+use crate::gpio::{Floating, Input, NoPin, OptionalPin, Output, PeripheralPin, PushPull};
+use nrf51::{uart0, GPIO, UART0};
+use void::{ResultVoidExt, Void};
 
 pub use nrf51::uart0::baudrate::BAUDRATE_A::{self, *};
 
 /// Serial abstraction
-pub struct Serial<UART> {
+///
+/// `TX` and `RX` are the types of the TXD and RXD pins, either numbered
+/// (e.g. `PIN24<Output<PushPull>>`) or erased. `RTS` and `CTS` are the types
+/// of the flow control pins, `NoPin` if there are none.
+///
+/// With the `embedded-io` feature, the `Tx` and `Rx` halves also implement
+/// the `embedded_io` `Write`/`WriteReady` and `Read`/`ReadReady` traits.
+pub struct Serial<
+    UART,
+    TX = PIN<Output<PushPull>>,
+    RX = PIN<Input<Floating>>,
+    RTS = NoPin,
+    CTS = NoPin,
+> {
     uart: UART,
-    txpin: PIN<Output<PushPull>>,
-    rxpin: PIN<Input<Floating>>,
+    pins: Pins<TX, RX, RTS, CTS>,
 }
 
+/// The pins used by a `Serial`
+///
+/// RTS and CTS are only needed for hardware flow control; pass `NoPin` for
+/// them otherwise.
+pub struct Pins<TX = PIN<Output<PushPull>>, RX = PIN<Input<Floating>>, RTS = NoPin, CTS = NoPin> {
+    /// Transmitted data
+    pub txd: TX,
+    /// Received data
+    pub rxd: RX,
+    /// Request to send, driven low by the UART while it can accept data
+    pub rts: RTS,
+    /// Clear to send, held low by the other side while it can accept data
+    pub cts: CTS,
+}
+
+/// Serial baud rate
+#[derive(Clone, Copy, Debug)]
+pub enum Baudrate {
+    /// One of the rates supported directly by the hardware
+    Standard(BAUDRATE_A),
+    /// Any other rate in baud, see `Tx::set_custom_baudrate`
+    Custom(u32),
+}
+
+/// Serial parity
+///
+/// The nRF51 UART only supports even parity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// An even parity bit after the data bits
+    Even,
+}
+
+/// Serial configuration, for `Serial::new`
+///
+/// `Config::default()` is 115200 baud, no parity and no flow control. Frames
+/// always have one stop bit, as the nRF51 UART doesn't support two.
+///
+/// ```ignore
+/// let config = Config::default()
+///     .baudrate(BAUD9600)
+///     .parity(Parity::Even);
+/// let serial = Serial::new(p.UART0, pins, config);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    baudrate: Baudrate,
+    parity: Parity,
+    flow_control: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            baudrate: Baudrate::Standard(BAUD115200),
+            parity: Parity::None,
+            flow_control: false,
+        }
+    }
+}
+
+impl Config {
+    /// Sets one of the baud rates supported directly by the hardware
+    pub fn baudrate(mut self, speed: BAUDRATE_A) -> Self {
+        self.baudrate = Baudrate::Standard(speed);
+        self
+    }
+
+    /// Sets any other baud rate, see `Tx::set_custom_baudrate`
+    pub fn custom_baudrate(mut self, baud: u32) -> Self {
+        self.baudrate = Baudrate::Custom(baud);
+        self
+    }
+
+    /// Sets the parity
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Enables or disables hardware flow control, which needs the RTS and
+    /// CTS pins
+    pub fn flow_control(mut self, enabled: bool) -> Self {
+        self.flow_control = enabled;
+        self
+    }
+}
+
+/// PSELx value leaving a signal not connected to any pin
+const PSEL_DISCONNECTED: u32 = 0xFFFF_FFFF;
+
+/// The peripheral and pins making up a `Serial<UART0>`
+type Uart0Parts<TX, RX> = (UART0, TX, RX);
+
 /// Serial receiver
+///
+/// The UART keeps receiving while nothing reads from it, e.g. while a long
+/// blocking write is in progress on the `Tx` half, but only holds a few
+/// received bytes. Any further byte overruns it and is lost. The overrun is
+/// reported by the next `read` as `Error::OVERRUN`, before the bytes that
+/// were kept, and counted by `rx_overrun_count()`.
+///
+/// The counts are kept across `into_interrupt()` and `RxInterrupt::free()`.
 pub struct Rx<UART> {
     _uart: PhantomData<UART>,
+    overruns: u32,
+    /// `RxInterrupt::dropped_bytes()`, kept for the next `into_interrupt()`
+    dropped: u32,
 }
 
 /// Serial transmitter
@@ -27,64 +147,588 @@ pub struct Tx<UART> {
     _uart: PhantomData<UART>,
 }
 
+// The halves are meant to be moved to interrupt handlers, see `split`
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Tx<UART0>>();
+    assert_send::<Rx<UART0>>();
+    assert_send::<RxInterrupt<UART0>>();
+};
+
+/// Serial error
 #[derive(Debug)]
-pub enum Error {}
+pub enum Error {
+    /// A byte was received before the previous one was read
+    OVERRUN,
+    /// A byte was received with the wrong parity
+    PARITY,
+    /// A valid stop bit wasn't received
+    FRAMING,
+    /// RXD was held low for longer than a whole frame
+    BREAK,
+}
+
+impl<TX, RX, RTS, CTS> Serial<UART0, TX, RX, RTS, CTS>
+where
+    TX: PeripheralPin<Output<PushPull>>,
+    RX: PeripheralPin<Input<Floating>>,
+    RTS: OptionalPin<Output<PushPull>>,
+    CTS: OptionalPin<Input<Floating>>,
+{
+    /// Configures and starts the UART
+    ///
+    /// The RTS and CTS pins are only connected if `config` enables hardware
+    /// flow control.
+    ///
+    /// # Panics
+    ///
+    /// Panics if hardware flow control is enabled without both an RTS and a
+    /// CTS pin.
+    pub fn new(uart: UART0, pins: Pins<TX, RX, RTS, CTS>, config: Config) -> Self {
+        let (rts, cts) = if config.flow_control {
+            match (pins.rts.optional_pin_id(), pins.cts.optional_pin_id()) {
+                (Some(rts), Some(cts)) => (u32::from(rts), u32::from(cts)),
+                _ => panic!("flow control needs both RTS and CTS pins"),
+            }
+        } else {
+            (PSEL_DISCONNECTED, PSEL_DISCONNECTED)
+        };
+
+        let txd = pins.txd.pin_id().into();
+        let rxd = pins.rxd.pin_id().into();
+        start_uart(&uart, [txd, rxd, rts, cts], config);
+
+        Serial { uart, pins }
+    }
+
+    /// Stops and disables the UART, returning the peripheral and pins
+    ///
+    /// The RX and TX tasks are stopped and the RXDRDY, TXDRDY and ERROR
+    /// events and any error flags cleared, so a following `uart0` starts from
+    /// a known state. The byte being sent (if any) is given time to go out
+    /// first, but like in `Tx::set_baudrate` the wait is bounded, so a byte
+    /// held back by flow control is cut off instead of hanging the call.
+    ///
+    /// The RTS and CTS pins, if any, are dropped; use `release_pins` to get
+    /// them back too.
+    pub fn release(self) -> Uart0Parts<TX, RX> {
+        let (uart, pins) = self.release_pins();
+        (uart, pins.txd, pins.rxd)
+    }
+
+    /// Like `release`, but returns all the pins
+    ///
+    /// The flow control and parity configuration is reset too.
+    pub fn release_pins(self) -> (UART0, Pins<TX, RX, RTS, CTS>) {
+        stop_uart(&self.uart);
+        (self.uart, self.pins)
+    }
+
+    /// Splits the UART into its transmitting and receiving halves
+    ///
+    /// The halves can be used from different contexts, e.g. `Rx` (or the
+    /// `RxInterrupt` made from it) moved into the UART0 interrupt handler
+    /// while `Tx` stays in the main loop. Both are `Send`, and they don't
+    /// share any register state:
+    ///
+    /// - `Tx` uses TXD, the TXDRDY event, the TX tasks, PSELTXD (for breaks)
+    ///   and BAUDRATE
+    /// - `Rx` uses RXD, the RXDRDY and ERROR events and ERRORSRC, and
+    ///   `RxInterrupt` the INTENSET/INTENCLR bits of those two events, which
+    ///   only affect the bits written
+    ///
+    /// The one shared setting is the baud rate: changing it through `Tx`
+    /// changes it for reception too, so only do that while nothing is being
+    /// received. The pins are dropped; use `release` instead if they're
+    /// needed again.
+    pub fn split(self) -> (Tx<UART0>, Rx<UART0>) {
+        (
+            Tx { _uart: PhantomData },
+            Rx {
+                _uart: PhantomData,
+                overruns: 0,
+                dropped: 0,
+            },
+        )
+    }
+
+    /// Changes the baud rate, see `Tx::set_baudrate`
+    pub fn set_baudrate(&mut self, speed: BAUDRATE_A) {
+        set_baudrate_bits(&self.uart, speed.into());
+    }
 
-impl Serial<UART0> {
-    pub fn uart0(
+    /// Changes the baud rate to a rate not in `BAUDRATE_A`, see
+    /// `Tx::set_custom_baudrate`
+    pub fn set_custom_baudrate(&mut self, baud: u32) {
+        set_baudrate_bits(&self.uart, custom_baudrate_bits(baud));
+    }
+}
+
+impl<TX, RX> Serial<UART0, TX, RX>
+where
+    TX: PeripheralPin<Output<PushPull>>,
+    RX: PeripheralPin<Input<Floating>>,
+{
+    /// Configures and starts the UART with the given baud rate, no parity
+    /// and no flow control
+    pub fn uart0(uart: UART0, txpin: TX, rxpin: RX, speed: BAUDRATE_A) -> Self {
+        let pins = Pins {
+            txd: txpin,
+            rxd: rxpin,
+            rts: NoPin,
+            cts: NoPin,
+        };
+        Self::new(uart, pins, Config::default().baudrate(speed))
+    }
+
+    /// Like `uart0`, but checks that both pins are valid pin numbers first
+    ///
+    /// If a pin number is out of range (0 ..= 31) the UART isn't touched and
+    /// the peripheral and pins are handed back as the error.
+    pub fn try_uart0(
         uart: UART0,
-        txpin: PIN<Output<PushPull>>,
-        rxpin: PIN<Input<Floating>>,
+        txpin: TX,
+        rxpin: RX,
         speed: BAUDRATE_A,
-    ) -> Self {
-        // Fill register with dummy data to trigger txd event
-        uart.txd.write(|w| unsafe { w.bits(0) });
+    ) -> core::result::Result<Self, Uart0Parts<TX, RX>> {
+        if txpin.pin_id() > 31 || rxpin.pin_id() > 31 {
+            return Err((uart, txpin, rxpin));
+        }
+        Ok(Self::uart0(uart, txpin, rxpin, speed))
+    }
+}
+
+/// Returns the BAUDRATE register value for `baud`
+///
+/// The register holds `baud * 2^32 / 16MHz`, of which only the upper 20 bits
+/// are used.
+fn custom_baudrate_bits(baud: u32) -> u32 {
+    let bits = (u64::from(baud) << 32) / 16_000_000;
+    ((bits + 0x800) & !0xFFF) as u32
+}
 
-        // Set output TXD and RXD pins
-        uart.pseltxd
-            .write(|w| unsafe { w.bits(txpin.get_id().into()) });
-        uart.pselrxd
-            .write(|w| unsafe { w.bits(rxpin.get_id().into()) });
+/// Configures and starts the UART, with the pin numbers (or
+/// `PSEL_DISCONNECTED`) of TXD, RXD, RTS and CTS in `psel`
+fn start_uart(uart: &uart0::RegisterBlock, psel: [u32; 4], config: Config) {
+    let [txd, rxd, rts, cts] = psel;
 
-        // Set baud rate
-        uart.baudrate.write(|w| w.baudrate().variant(speed));
+    // Fill register with dummy data to trigger txd event
+    uart.txd.write(|w| unsafe { w.bits(0) });
 
-        // Enable UART function
-        uart.enable.write(|w| w.enable().enabled());
+    // Set output TXD and RXD pins, and RTS and CTS for flow control
+    uart.pseltxd.write(|w| unsafe { w.bits(txd) });
+    uart.pselrxd.write(|w| unsafe { w.bits(rxd) });
+    uart.pselrts.write(|w| unsafe { w.bits(rts) });
+    uart.pselcts.write(|w| unsafe { w.bits(cts) });
 
-        // Fire up transmitting and receiving task
-        uart.tasks_starttx.write(|w| unsafe { w.bits(1) });
-        uart.tasks_startrx.write(|w| unsafe { w.bits(1) });
+    uart.config.write(|w| {
+        let w = w.hwfc().bit(config.flow_control);
+        match config.parity {
+            Parity::None => w.parity().excluded(),
+            Parity::Even => w.parity().included(),
+        }
+    });
 
-        Serial { uart, txpin, rxpin }
+    // Set baud rate
+    match config.baudrate {
+        Baudrate::Standard(speed) => uart.baudrate.write(|w| w.baudrate().variant(speed)),
+        Baudrate::Custom(baud) => uart
+            .baudrate
+            .write(|w| unsafe { w.bits(custom_baudrate_bits(baud)) }),
     }
 
-    pub fn release(self) -> (UART0, PIN<Output<PushPull>>, PIN<Input<Floating>>) {
-        (self.uart, self.txpin, self.rxpin)
+    // Enable UART function
+    uart.enable.write(|w| w.enable().enabled());
+
+    // Fire up transmitting and receiving task
+    uart.tasks_starttx.write(|w| unsafe { w.bits(1) });
+    uart.tasks_startrx.write(|w| unsafe { w.bits(1) });
+}
+
+/// Stops and disables the UART, clearing its events and error flags and
+/// resetting the flow control and parity configuration
+fn stop_uart(uart: &uart0::RegisterBlock) {
+    // Let the byte being sent (if any) go out before stopping
+    wait_tx_idle(uart);
+
+    uart.tasks_stoptx.write(|w| unsafe { w.bits(1) });
+    uart.tasks_stoprx.write(|w| unsafe { w.bits(1) });
+    uart.enable.write(|w| w.enable().disabled());
+
+    uart.events_txdrdy.reset();
+    uart.events_rxdrdy.reset();
+    uart.events_error.reset();
+    let errorsrc = uart.errorsrc.read().bits();
+    uart.errorsrc.write(|w| unsafe { w.bits(errorsrc) });
+
+    uart.config.reset();
+    uart.pselrts.write(|w| unsafe { w.bits(PSEL_DISCONNECTED) });
+    uart.pselcts.write(|w| unsafe { w.bits(PSEL_DISCONNECTED) });
+}
+
+/// Waits for the byte being sent (if any) to go out, then writes BAUDRATE
+fn set_baudrate_bits(uart: &uart0::RegisterBlock, bits: u32) {
+    wait_tx_idle(uart);
+    uart.baudrate.write(|w| unsafe { w.bits(bits) });
+}
+
+/// Upper bound on the polls waiting for TXDRDY
+///
+/// A poll takes at least 4 cycles of the 16MHz core clock, so this allows over
+/// 10ms, more than the 9.2ms an 11 bit frame takes at 1200 baud.
+const MAX_TXDRDY_POLLS: u32 = 40_000;
+
+/// Waits for the byte being sent (if any) to go out, returning false if it
+/// didn't within `MAX_TXDRDY_POLLS`
+///
+/// With flow control a byte is held back for as long as CTS is inactive, so
+/// the wait has to be bounded.
+fn wait_tx_idle(uart: &uart0::RegisterBlock) -> bool {
+    (0..MAX_TXDRDY_POLLS).any(|_| uart.events_txdrdy.read().bits() != 0)
+}
+
+impl Tx<UART0> {
+    /// Returns true if the transmitter can accept the next byte
+    ///
+    /// Reads the TXDRDY event without clearing it, so a following `write`
+    /// won't block.
+    pub fn tx_ready(&self) -> bool {
+        let uart = unsafe { &*UART0::ptr() };
+        uart.events_txdrdy.read().bits() != 0
     }
 
-    pub fn split(self) -> (Tx<UART0>, Rx<UART0>) {
-        (Tx { _uart: PhantomData }, Rx { _uart: PhantomData })
+    /// Sends a byte, waiting until the transmitter can accept it
+    ///
+    /// This is the same as `block!(tx.write(byte))`, for when there is no
+    /// need to do anything else in the meantime.
+    pub fn write_blocking(&mut self, byte: u8) {
+        use embedded_hal::serial::Write;
+        block!(self.write(byte)).void_unwrap()
+    }
+
+    /// Sends all bytes of `buffer`, waiting until each one is accepted
+    pub fn write_all_blocking(&mut self, buffer: &[u8]) {
+        for byte in buffer {
+            self.write_blocking(*byte);
+        }
+    }
+
+    /// Changes the baud rate of both halves
+    ///
+    /// Waits for the byte being sent (if any) to go out completely, then
+    /// changes the rate; the UART doesn't need to be stopped for that. The wait
+    /// is bounded, so a byte held back by flow control (or still being sent at
+    /// a custom rate below 1200 baud) doesn't hang the call but may go out at
+    /// the new rate. A byte
+    /// being received at the time of the change is likely to be corrupted or
+    /// reported as a framing error, so only change the rate when the other
+    /// side is known to be quiet, e.g. after the handshake negotiating it.
+    pub fn set_baudrate(&mut self, speed: BAUDRATE_A) {
+        let uart = unsafe { &*UART0::ptr() };
+        set_baudrate_bits(uart, speed.into());
+    }
+
+    /// Changes the baud rate of both halves to a rate not in `BAUDRATE_A`
+    ///
+    /// The register value is calculated from `baud` and rounded to the
+    /// precision of the hardware, so the actual rate may differ slightly. See
+    /// `set_baudrate` for the effect on the line.
+    pub fn set_custom_baudrate(&mut self, baud: u32) {
+        let uart = unsafe { &*UART0::ptr() };
+        set_baudrate_bits(uart, custom_baudrate_bits(baud));
+    }
+
+    /// Sends a break condition
+    ///
+    /// Waits for the byte currently being sent (if any) to go out, then holds
+    /// TXD low for at least 13 bit periods at the configured baud rate (the
+    /// minimum break length required by LIN), followed by at least one bit
+    /// period of idle level. At 9600 baud the break lasts at least 1.35ms, at
+    /// 19200 baud 680µs and at 115200 baud 113µs.
+    ///
+    /// The timing is a busy-wait on the 16MHz core clock, so interrupts
+    /// serviced in the meantime will make the break longer. Like
+    /// `set_baudrate`, the wait for the byte being sent is bounded.
+    ///
+    /// Does nothing if TXD is disconnected or the baud rate is set to 0.
+    pub fn send_break(&mut self) {
+        let uart = unsafe { &*UART0::ptr() };
+        let gpio = unsafe { &*GPIO::ptr() };
+
+        let pin = uart.pseltxd.read().bits();
+        let baudrate = uart.baudrate.read().bits();
+        if pin == PSEL_DISCONNECTED || baudrate == 0 {
+            return;
+        }
+
+        // Wait for the last byte to be sent completely
+        wait_tx_idle(uart);
+
+        // BAUDRATE holds baud * 2^32 / 16MHz, so one bit period lasts
+        // 2^32 / BAUDRATE core clock cycles
+        let bit_cycles = ((1_u64 << 32) / u64::from(baudrate)) as u32;
+
+        // Disconnect TXD from the UART and drive the (output) pin low directly
+        gpio.outclr.write(|w| unsafe { w.bits(1 << pin) });
+        uart.pseltxd.write(|w| unsafe { w.bits(PSEL_DISCONNECTED) });
+        busy_wait_cycles(13_u32.saturating_mul(bit_cycles));
+
+        // Return to idle level and give the pin back to the UART
+        gpio.outset.write(|w| unsafe { w.bits(1 << pin) });
+        busy_wait_cycles(bit_cycles);
+        uart.pseltxd.write(|w| unsafe { w.bits(pin) });
+    }
+}
+
+impl Rx<UART0> {
+    /// Returns true if a received byte is waiting to be read
+    ///
+    /// Reads the RXDRDY event without clearing it, so a following `read`
+    /// won't block (though it may report a pending reception error first).
+    pub fn has_rx_data(&self) -> bool {
+        let uart = unsafe { &*UART0::ptr() };
+        uart.events_rxdrdy.read().bits() != 0
+    }
+
+    /// Returns the number of overruns reported by `read` so far, including
+    /// those seen by `RxInterrupt` in the meantime
+    ///
+    /// Each overrun lost at least one received byte, so a non-zero count
+    /// explains a protocol getting out of step. The count wraps around.
+    pub fn rx_overrun_count(&self) -> u32 {
+        self.overruns
+    }
+
+    /// Reads received bytes into `buffer`, returning how many were read
+    ///
+    /// Waits until at least one byte has been received, then reads as many
+    /// more as are available without waiting, up to the length of `buffer`.
+    /// Returns `Ok(0)` only for an empty `buffer`.
+    ///
+    /// A reception error is only returned if it's pending before the first
+    /// byte. One raised after some bytes were read ends the read early and
+    /// those bytes are returned; the error is left pending for the next call,
+    /// unless it's raised just as the next byte is read.
+    pub fn read_bytes(&mut self, buffer: &mut [u8]) -> core::result::Result<usize, Error> {
+        use embedded_hal::serial::Read;
+
+        let uart = unsafe { &*UART0::ptr() };
+
+        let (first, rest) = match buffer.split_first_mut() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        *first = block!(self.read())?;
+
+        let mut count = 1;
+        for byte in rest {
+            if uart.events_error.read().bits() != 0 {
+                break;
+            }
+            match self.read() {
+                Ok(b) => *byte = b,
+                // An error raised between the check above and the read has
+                // been cleared by now; the bytes read take precedence
+                Err(_) => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Clears and decodes a pending reception error, if any
+///
+/// Returns the error to report and whether an overrun was flagged. The
+/// OVERRUN flag is raised independently of the others, so a byte may have
+/// been lost even when e.g. `Error::FRAMING` is reported. The zero byte
+/// received along with a break or framing error is discarded.
+fn take_rx_error(uart: &uart0::RegisterBlock) -> Option<(Error, bool)> {
+    if uart.events_error.read().bits() == 0 {
+        return None;
+    }
+    uart.events_error.reset();
+
+    // Error source bits are cleared by writing 1 to them
+    let errorsrc = uart.errorsrc.read();
+    uart.errorsrc.write(|w| unsafe { w.bits(errorsrc.bits()) });
+
+    let error = if errorsrc.break_().is_present() {
+        Error::BREAK
+    } else if errorsrc.framing().is_present() {
+        Error::FRAMING
+    } else if errorsrc.parity().is_present() {
+        Error::PARITY
+    } else {
+        Error::OVERRUN
+    };
+
+    if let Error::BREAK | Error::FRAMING = error {
+        if uart.events_rxdrdy.read().bits() != 0 {
+            uart.events_rxdrdy.reset();
+            let _ = uart.rxd.read();
+        }
+    }
+
+    Some((error, errorsrc.overrun().is_present()))
+}
+
+/// Interrupt-driven serial receiver
+///
+/// Created by `Rx::into_interrupt()`, which enables the RXDRDY and ERROR
+/// interrupts; the UART0 interrupt must also be unmasked in the NVIC. Call
+/// `on_interrupt()` from the UART0 interrupt handler to move received bytes
+/// into a queue, and take them out of the queue in the main loop. With a
+/// single-producer single-consumer queue such as `heapless::spsc::Queue` the
+/// main loop doesn't need a critical section to take bytes out:
+///
+/// ```ignore
+/// static mut QUEUE: Queue<u8, 64> = Queue::new();
+/// static RX: Mutex<RefCell<Option<RxInterrupt<UART0>>>> = ...;
+///
+/// #[interrupt]
+/// fn UART0() {
+///     static mut PRODUCER: Option<Producer<'static, u8, 64>> = None;
+///     // PRODUCER is filled in from main() after QUEUE.split()
+///     let producer = PRODUCER.as_mut().unwrap();
+///     cortex_m::interrupt::free(|cs| {
+///         if let Some(rx) = RX.borrow(cs).borrow_mut().as_mut() {
+///             rx.on_interrupt(|byte| producer.enqueue(byte).is_ok());
+///         }
+///     });
+/// }
+///
+/// // In the main loop
+/// while let Some(byte) = consumer.dequeue() {
+///     handle(byte);
+/// }
+/// ```
+///
+/// The hardware only holds a few received bytes, so the interrupt must be
+/// serviced promptly at high baud rates. Bytes lost either in the hardware
+/// (an overrun) or because the queue was full are counted by
+/// `dropped_bytes()`, and overruns also by `rx_overrun_count()`. Both counts
+/// carry on from (and back to) the polled `Rx`.
+pub struct RxInterrupt<UART> {
+    _uart: PhantomData<UART>,
+    overruns: u32,
+    dropped: u32,
+    error: Option<Error>,
+}
+
+impl Rx<UART0> {
+    /// Enables the RXDRDY and ERROR interrupts, for reception from the UART0
+    /// interrupt handler
+    pub fn into_interrupt(self) -> RxInterrupt<UART0> {
+        let uart = unsafe { &*UART0::ptr() };
+        uart.intenset
+            .write(|w| w.rxdrdy().set_bit().error().set_bit());
+        RxInterrupt {
+            _uart: PhantomData,
+            overruns: self.overruns,
+            dropped: self.dropped,
+            error: None,
+        }
+    }
+}
+
+impl RxInterrupt<UART0> {
+    /// Handles the UART0 interrupt
+    ///
+    /// Passes every received byte to `push`, which returns false if it has
+    /// no room for the byte: the byte is then dropped and counted. Reception
+    /// errors are cleared and the latest kept for `take_error()`; an overrun
+    /// is also counted as a dropped byte, even if flagged along with another
+    /// error.
+    pub fn on_interrupt<F>(&mut self, mut push: F)
+    where
+        F: FnMut(u8) -> bool,
+    {
+        let uart = unsafe { &*UART0::ptr() };
+        loop {
+            if let Some((error, overrun)) = take_rx_error(uart) {
+                if overrun {
+                    self.overruns = self.overruns.wrapping_add(1);
+                    self.dropped = self.dropped.wrapping_add(1);
+                }
+                self.error = Some(error);
+            }
+
+            if uart.events_rxdrdy.read().bits() == 0 {
+                break;
+            }
+            uart.events_rxdrdy.reset();
+            let byte = uart.rxd.read().bits() as u8;
+            if !push(byte) {
+                self.dropped = self.dropped.wrapping_add(1);
+            }
+        }
+    }
+
+    /// Returns the number of received bytes lost so far
+    ///
+    /// Counts bytes lost to a hardware overrun and bytes rejected by the
+    /// queue. The count wraps around.
+    pub fn dropped_bytes(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the number of overruns so far, in this mode and while polled
+    ///
+    /// See `Rx::rx_overrun_count()`. The count wraps around.
+    pub fn rx_overrun_count(&self) -> u32 {
+        self.overruns
+    }
+
+    /// Returns and clears the latest reception error
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Disables the RXDRDY and ERROR interrupts, returning the polled
+    /// receiver
+    pub fn free(self) -> Rx<UART0> {
+        let uart = unsafe { &*UART0::ptr() };
+        uart.intenclr
+            .write(|w| w.rxdrdy().set_bit().error().set_bit());
+        Rx {
+            _uart: PhantomData,
+            overruns: self.overruns,
+            dropped: self.dropped,
+        }
     }
 }
 
 impl embedded_hal::serial::Read<u8> for Rx<UART0> {
     type Error = Error;
 
+    /// Reads a received byte
+    ///
+    /// Reception errors are reported (and cleared) before any data. A break
+    /// is reported as `Error::BREAK`, and the zero byte received along with
+    /// a break or framing error is discarded. Overruns are also counted, see
+    /// `rx_overrun_count()`, even if flagged along with another error.
     fn read(&mut self) -> nb::Result<u8, Self::Error> {
         let uart = unsafe { &*UART0::ptr() };
-        match uart.events_rxdrdy.read().bits() {
-            0 => Err(nb::Error::WouldBlock),
-            _ => {
-                // Reset ready for receive event
-                uart.events_rxdrdy.reset();
 
-                // Read one 8bit value
-                let byte = uart.rxd.read().bits() as u8;
-
-                Ok(byte)
+        if let Some((error, overrun)) = take_rx_error(uart) {
+            if overrun {
+                self.overruns = self.overruns.wrapping_add(1);
             }
+            return Err(nb::Error::Other(error));
+        }
+
+        if !self.has_rx_data() {
+            return Err(nb::Error::WouldBlock);
         }
+
+        // Reset ready for receive event
+        uart.events_rxdrdy.reset();
+
+        // Read one 8bit value
+        let byte = uart.rxd.read().bits() as u8;
+
+        Ok(byte)
     }
 }
 
@@ -98,7 +742,7 @@ impl embedded_hal::serial::Write<u8> for Tx<UART0> {
     fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
         let uart = unsafe { &*UART0::ptr() };
         // Are we ready for sending out next byte?
-        if uart.events_txdrdy.read().bits() == 1 {
+        if self.tx_ready() {
             // Reset ready for transmit event
             uart.events_txdrdy.reset();
 
@@ -117,9 +761,197 @@ impl<UART> Write for Tx<UART>
 where
     Tx<UART>: embedded_hal::serial::Write<u8>,
 {
+    /// Writes the string byte by byte, blocking until each one is accepted
+    ///
+    /// Nothing is buffered. The first serial error aborts the write and is
+    /// reported as `core::fmt::Error`; for an infallible transmitter (like
+    /// `Tx<UART0>`, whose error type is `Void`) the error path compiles away.
     fn write_str(&mut self, s: &str) -> Result {
         use embedded_hal::serial::Write;
-        let _ = s.as_bytes().iter().map(|c| block!(self.write(*c))).last();
+        for c in s.as_bytes() {
+            block!(self.write(*c)).map_err(|_| core::fmt::Error)?;
+        }
         Ok(())
     }
 }
+
+/// Maps the reception errors to `embedded_io` error kinds
+///
+/// Parity and framing errors mean a corrupted byte; overruns and breaks
+/// don't fit any specific kind.
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::PARITY | Error::FRAMING => embedded_io::ErrorKind::InvalidData,
+            Error::OVERRUN | Error::BREAK => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ErrorType for Rx<UART0> {
+    type Error = Error;
+}
+
+/// Only available with the `embedded-io` feature.
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Read for Rx<UART0> {
+    /// Reads received bytes into `buf`, see `read_bytes`
+    fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, Error> {
+        self.read_bytes(buf)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ReadReady for Rx<UART0> {
+    /// See `has_rx_data`
+    fn read_ready(&mut self) -> core::result::Result<bool, Error> {
+        Ok(self.has_rx_data())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ErrorType for Tx<UART0> {
+    type Error = core::convert::Infallible;
+}
+
+/// Only available with the `embedded-io` feature.
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Write for Tx<UART0> {
+    /// Sends all of `buf`, see `write_all_blocking`
+    fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Self::Error> {
+        self.write_all_blocking(buf);
+        Ok(buf.len())
+    }
+
+    /// Waits for the last byte written to be sent
+    fn flush(&mut self) -> core::result::Result<(), Self::Error> {
+        while !self.tx_ready() {}
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::WriteReady for Tx<UART0> {
+    /// See `tx_ready`
+    fn write_ready(&mut self) -> core::result::Result<bool, Self::Error> {
+        Ok(self.tx_ready())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_baudrate_bits_matches_the_standard_rates() {
+        let rates = [
+            (1200, BAUD1200),
+            (2400, BAUD2400),
+            (4800, BAUD4800),
+            (9600, BAUD9600),
+            (14400, BAUD14400),
+            (19200, BAUD19200),
+            (28800, BAUD28800),
+            (38400, BAUD38400),
+            (57600, BAUD57600),
+            (76800, BAUD76800),
+            (115_200, BAUD115200),
+            (230_400, BAUD230400),
+            (250_000, BAUD250000),
+            (460_800, BAUD460800),
+            (1_000_000, BAUD1M),
+        ];
+        for (baud, standard) in rates.iter() {
+            assert_eq!(
+                custom_baudrate_bits(*baud),
+                u32::from(*standard),
+                "{} baud",
+                baud
+            );
+        }
+    }
+
+    #[test]
+    fn custom_baudrate_bits_rounds_921600_to_nearest() {
+        // The exact value is 0x0EBE_DFA4. Nordic's table has 0x0EBE_D000,
+        // one step lower; the nearest step is 0x0EBE_E000
+        assert_eq!(u32::from(BAUD921600), 0x0EBE_D000);
+        assert_eq!(custom_baudrate_bits(921_600), 0x0EBE_E000);
+    }
+
+    /// Reads back the value last written to a write-only register
+    fn written<T>(reg: &T) -> u32 {
+        unsafe { (reg as *const T as *const u32).read_volatile() }
+    }
+
+    #[test]
+    fn take_rx_error_reports_overrun_alongside_other_errors() {
+        let uart: uart0::RegisterBlock = unsafe { core::mem::zeroed() };
+        assert!(take_rx_error(&uart).is_none());
+
+        // ERRORSRC bits: OVERRUN, PARITY, FRAMING, BREAK
+        let cases = [
+            (0b0001, Error::OVERRUN, true),
+            (0b0010, Error::PARITY, false),
+            (0b0011, Error::PARITY, true),
+            (0b0101, Error::FRAMING, true),
+            (0b1101, Error::BREAK, true),
+            (0b1000, Error::BREAK, false),
+        ];
+        for (errorsrc, expected, expected_overrun) in cases.iter() {
+            uart.events_error.write(|w| unsafe { w.bits(1) });
+            uart.errorsrc.write(|w| unsafe { w.bits(*errorsrc) });
+            let (error, overrun) = take_rx_error(&uart).unwrap();
+            assert_eq!(
+                core::mem::discriminant(&error),
+                core::mem::discriminant(expected),
+                "ERRORSRC {:#b}",
+                errorsrc
+            );
+            assert_eq!(overrun, *expected_overrun, "ERRORSRC {:#b}", errorsrc);
+            assert_eq!(uart.events_error.read().bits(), 0);
+        }
+    }
+
+    #[test]
+    fn stop_uart_leaves_nothing_behind_for_the_next_start() {
+        // Zeroed memory standing in for the UART registers
+        let uart: uart0::RegisterBlock = unsafe { core::mem::zeroed() };
+
+        let hwfc = Config::default().baudrate(BAUD9600).flow_control(true);
+        start_uart(&uart, [1, 2, 3, 4], hwfc.parity(Parity::Even));
+        assert!(uart.config.read().hwfc().is_enabled());
+        assert!(uart.enable.read().enable().is_enabled());
+
+        // As left by the hardware after receiving a byte with an error
+        uart.events_txdrdy.write(|w| unsafe { w.bits(1) });
+        uart.events_rxdrdy.write(|w| unsafe { w.bits(1) });
+        uart.events_error.write(|w| unsafe { w.bits(1) });
+
+        stop_uart(&uart);
+        assert_eq!(written(&uart.tasks_stoptx), 1);
+        assert_eq!(written(&uart.tasks_stoprx), 1);
+        assert!(uart.enable.read().enable().is_disabled());
+        assert_eq!(uart.events_txdrdy.read().bits(), 0);
+        assert_eq!(uart.events_rxdrdy.read().bits(), 0);
+        assert_eq!(uart.events_error.read().bits(), 0);
+        assert_eq!(uart.config.read().bits(), 0);
+        assert_eq!(uart.pselrts.read().bits(), PSEL_DISCONNECTED);
+        assert_eq!(uart.pselcts.read().bits(), PSEL_DISCONNECTED);
+
+        // Nothing stale may make the first byte after restarting look sent
+        // already; TXDRDY only comes from the dummy byte written on start
+        let plain = [5, 6, PSEL_DISCONNECTED, PSEL_DISCONNECTED];
+        start_uart(&uart, plain, Config::default());
+        assert_eq!(uart.events_txdrdy.read().bits(), 0);
+        assert_eq!(uart.events_rxdrdy.read().bits(), 0);
+        assert_eq!(uart.pseltxd.read().bits(), 5);
+        assert_eq!(uart.pselrxd.read().bits(), 6);
+        assert_eq!(uart.config.read().bits(), 0);
+        assert_eq!(uart.baudrate.read().bits(), u32::from(BAUD115200));
+        assert_eq!(written(&uart.tasks_starttx), 1);
+        assert!(uart.enable.read().enable().is_enabled());
+    }
+}
@@ -2,24 +2,41 @@
 //!
 
 use crate::gpio::gpio::PIN;
-use crate::gpio::{Floating, Input, Output, PushPull};
-use nrf51::{spi0, SPI0, SPI1};
+use crate::gpio::{Floating, Input, Output, PeripheralPin, PushPull};
+use nrf51::spi0::frequency;
+use nrf51::{spi0, GPIO, SPI0, SPI1};
 
 use embedded_hal::blocking::spi::{transfer, write, write_iter};
-use embedded_hal::spi::FullDuplex;
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::spi::{FullDuplex, Mode, Phase, Polarity, MODE_0};
 
 use core::ops::Deref;
 
+/// PSEL value for a disconnected signal
+const PSEL_DISCONNECTED: u32 = 0xFFFF_FFFF;
+
 /// SPI abstraction
-pub struct Spi<SPI> {
+///
+/// `SCK`, `MOSI` and `MISO` are the types of the pins, either numbered (e.g.
+/// `PIN23<Output<PushPull>>`) or erased.
+pub struct Spi<
+    SPI,
+    SCK = PIN<Output<PushPull>>,
+    MOSI = PIN<Output<PushPull>>,
+    MISO = PIN<Input<Floating>>,
+> {
     spi: SPI,
-    pins: Pins,
+    pins: Pins<SCK, MOSI, MISO>,
 }
 
-pub struct Pins {
-    pub sck: PIN<Output<PushPull>>,
-    pub mosi: PIN<Output<PushPull>>,
-    pub miso: PIN<Input<Floating>>,
+pub struct Pins<
+    SCK = PIN<Output<PushPull>>,
+    MOSI = PIN<Output<PushPull>>,
+    MISO = PIN<Input<Floating>>,
+> {
+    pub sck: SCK,
+    pub mosi: MOSI,
+    pub miso: MISO,
 }
 
 #[derive(Debug)]
@@ -28,15 +45,101 @@ pub enum Error {
     NACK,
 }
 
+/// SPI clock frequency
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frequency {
+    K125,
+    K250,
+    K500,
+    M1,
+    M2,
+    M4,
+    M8,
+}
+
+impl From<Frequency> for frequency::FREQUENCY_A {
+    fn from(frequency: Frequency) -> Self {
+        match frequency {
+            Frequency::K125 => frequency::FREQUENCY_A::K125,
+            Frequency::K250 => frequency::FREQUENCY_A::K250,
+            Frequency::K500 => frequency::FREQUENCY_A::K500,
+            Frequency::M1 => frequency::FREQUENCY_A::M1,
+            Frequency::M2 => frequency::FREQUENCY_A::M2,
+            Frequency::M4 => frequency::FREQUENCY_A::M4,
+            Frequency::M8 => frequency::FREQUENCY_A::M8,
+        }
+    }
+}
+
+/// Order in which the bits of each byte are shifted out and in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+/// SPI configuration, for `Spi::new_with_config`
+///
+/// `SpiConfig::default()` is mode 0 at 4MHz, MSB first, as used by `new`.
+#[derive(Clone, Copy)]
+pub struct SpiConfig {
+    pub mode: Mode,
+    pub frequency: Frequency,
+    pub bit_order: BitOrder,
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        SpiConfig {
+            mode: MODE_0,
+            frequency: Frequency::M4,
+            bit_order: BitOrder::MsbFirst,
+        }
+    }
+}
+
+/// Writes the CONFIG and FREQUENCY registers from `config`
+fn configure(spi: &spi0::RegisterBlock, config: &SpiConfig) {
+    spi.config.write(|w| {
+        let w = match config.bit_order {
+            BitOrder::MsbFirst => w.order().msb_first(),
+            BitOrder::LsbFirst => w.order().lsb_first(),
+        };
+        let w = match config.mode.phase {
+            Phase::CaptureOnFirstTransition => w.cpha().leading(),
+            Phase::CaptureOnSecondTransition => w.cpha().trailing(),
+        };
+        match config.mode.polarity {
+            Polarity::IdleLow => w.cpol().active_high(),
+            Polarity::IdleHigh => w.cpol().active_low(),
+        }
+    });
+
+    spi.frequency
+        .write(|w| w.frequency().variant(config.frequency.into()));
+}
+
 pub trait SpiExt: Deref<Target = spi0::RegisterBlock> + Sized {
-    fn constrain(self, pins: Pins) -> Spi<Self>;
+    fn constrain<SCK, MOSI, MISO>(self, pins: Pins<SCK, MOSI, MISO>) -> Spi<Self, SCK, MOSI, MISO>
+    where
+        SCK: PeripheralPin<Output<PushPull>>,
+        MOSI: PeripheralPin<Output<PushPull>>,
+        MISO: PeripheralPin<Input<Floating>>;
 }
 
 macro_rules! impl_spi_ext {
     ($($spi:ty,)*) => {
         $(
             impl SpiExt for $spi {
-                fn constrain(self, pins: Pins) -> Spi<Self> {
+                fn constrain<SCK, MOSI, MISO>(
+                    self,
+                    pins: Pins<SCK, MOSI, MISO>,
+                ) -> Spi<Self, SCK, MOSI, MISO>
+                where
+                    SCK: PeripheralPin<Output<PushPull>>,
+                    MOSI: PeripheralPin<Output<PushPull>>,
+                    MISO: PeripheralPin<Input<Floating>>,
+                {
                     Spi::new(self, pins)
                 }
             }
@@ -46,9 +149,12 @@ macro_rules! impl_spi_ext {
 
 impl_spi_ext!(SPI0, SPI1,);
 
-impl<SPI> Spi<SPI>
+impl<SPI, SCK, MOSI, MISO> Spi<SPI, SCK, MOSI, MISO>
 where
     SPI: SpiExt,
+    SCK: PeripheralPin<Output<PushPull>>,
+    MOSI: PeripheralPin<Output<PushPull>>,
+    MISO: PeripheralPin<Input<Floating>>,
 {
     /// Interface to a SPI instance
     ///
@@ -59,62 +165,219 @@ where
     /// conflicting instances are disabled before using `SPI`. Please refer to the
     /// product specification for more details
 
-    pub fn new(spi: SPI, pins: Pins) -> Self
-    where
-        SPI: SpiExt,
-    {
+    pub fn new(spi: SPI, pins: Pins<SCK, MOSI, MISO>) -> Self {
+        Self::new_with_config(spi, pins, SpiConfig::default())
+    }
+
+    /// Interface to a SPI instance, with the given mode, frequency and bit
+    /// order
+    ///
+    /// The same limitations as for `new` apply.
+    pub fn new_with_config(spi: SPI, pins: Pins<SCK, MOSI, MISO>, config: SpiConfig) -> Self {
         // Select pins
         spi.pselsck
-            .write(|w| unsafe { w.bits(pins.sck.get_id().into()) });
+            .write(|w| unsafe { w.bits(pins.sck.pin_id().into()) });
         spi.pselmosi
-            .write(|w| unsafe { w.bits(pins.mosi.get_id().into()) });
+            .write(|w| unsafe { w.bits(pins.mosi.pin_id().into()) });
         spi.pselmiso
-            .write(|w| unsafe { w.bits(pins.miso.get_id().into()) });
+            .write(|w| unsafe { w.bits(pins.miso.pin_id().into()) });
 
         // Enable SPIM instance
         spi.enable.write(|w| w.enable().enabled());
 
-        // Set to SPI mode 0
-        spi.config
-            .write(|w| w.order().msb_first().cpha().leading().cpol().active_high());
-
-        // Configure frequency
-        spi.frequency.write(
-            |w| w.frequency().m4(), // 4MHz
-        );
+        configure(&spi, &config);
 
         Spi {
             spi: spi,
             pins: pins,
         }
     }
-    pub fn teardown(self) -> Pins {
+    pub fn teardown(self) -> Pins<SCK, MOSI, MISO> {
         self.pins
     }
+
+    /// Runs `f` with chip select asserted
+    ///
+    /// Drives `cs` low, runs `f` (which can issue any number of transfers) and
+    /// drives `cs` high again once `f` returns, whatever it returns. This keeps
+    /// the chip selected across multi-step command sequences, e.g. for flash
+    /// erase and program operations.
+    ///
+    /// Returns the result of `f`, or the error raised while driving `cs`.
+    pub fn transaction<CS, F, R>(&mut self, cs: &mut CS, f: F) -> Result<R, CS::Error>
+    where
+        CS: OutputPin,
+        F: FnOnce(&mut Self) -> R,
+    {
+        cs.set_low()?;
+        let res = f(self);
+        cs.set_high()?;
+        Ok(res)
+    }
+
+    /// Writes `data`, discarding the received bytes
+    ///
+    /// Unlike the blocking `Write` implementation, which waits for each byte
+    /// to be clocked out before sending the next one, this keeps both TX
+    /// buffers filled, so there is no gap on the bus between bytes. Use it
+    /// for streaming large buffers, e.g. a display framebuffer.
+    pub fn write_bulk(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut bytes = data.iter();
+        let mut pending = 0;
+
+        // Fill both TX buffers
+        for &byte in bytes.by_ref().take(2) {
+            self.spi.txd.write(|w| unsafe { w.bits(u32::from(byte)) });
+            pending += 1;
+        }
+
+        // Queue a new byte as soon as one has been transferred
+        for &byte in bytes {
+            self.discard_received();
+            self.spi.txd.write(|w| unsafe { w.bits(u32::from(byte)) });
+        }
+
+        // Drain the bytes still in flight
+        for _ in 0..pending {
+            self.discard_received();
+        }
+
+        Ok(())
+    }
+
+    fn discard_received(&mut self) {
+        while self.spi.events_ready.read().bits() == 0 {}
+        // Clear the event before reading RXD, as reading it moves the next
+        // received byte (if any) in and generates a new READY event
+        self.spi.events_ready.reset();
+        let _ = self.spi.rxd.read().bits();
+    }
+}
+/// SPI abstraction for 3-wire devices, created by `Spi::new_3wire`
+///
+/// Every byte is a full transfer with SCK idling in between, so writes and
+/// reads are slower than with `Spi`. The data line changes direction between
+/// the last written and first read byte, while SCK is idle; this suits
+/// devices which release the line after the command byte, but not ones that
+/// turn the line around in the middle of a byte or expect a dummy clock
+/// cycle (there's no way to send a partial byte). The line floats for the
+/// turnaround, so a device that doesn't drive it immediately may need a pull
+/// resistor.
+pub struct Spi3Wire<SPI, SCK = PIN<Output<PushPull>>, SDIO = PIN<Output<PushPull>>> {
+    spi: SPI,
+    sck: SCK,
+    sdio: SDIO,
+}
+
+impl<SPI> Spi<SPI>
+where
+    SPI: SpiExt,
+{
+    /// Interface to a SPI instance for 3-wire devices, which use a single
+    /// bidirectional data line
+    ///
+    /// The SPI peripheral doesn't support this natively, so `sdio` is driven
+    /// as MOSI while writing and switched to an input and connected as MISO
+    /// while reading. The same limitations as for `new` apply.
+    pub fn new_3wire<SCK, SDIO>(spi: SPI, sck: SCK, sdio: SDIO) -> Spi3Wire<SPI, SCK, SDIO>
+    where
+        SCK: PeripheralPin<Output<PushPull>>,
+        SDIO: PeripheralPin<Output<PushPull>>,
+    {
+        spi.pselsck
+            .write(|w| unsafe { w.bits(sck.pin_id().into()) });
+        spi.pselmosi
+            .write(|w| unsafe { w.bits(sdio.pin_id().into()) });
+        spi.pselmiso.write(|w| unsafe { w.bits(PSEL_DISCONNECTED) });
+
+        spi.enable.write(|w| w.enable().enabled());
+
+        // Mode 0 at 4MHz, MSB first
+        configure(&spi, &SpiConfig::default());
+
+        Spi3Wire { spi, sck, sdio }
+    }
 }
+
+impl<SPI, SCK, SDIO> Spi3Wire<SPI, SCK, SDIO>
+where
+    SPI: SpiExt,
+    SDIO: PeripheralPin<Output<PushPull>>,
+{
+    /// Writes `bytes`, driving the data line
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        for byte in bytes {
+            self.transfer_byte(*byte);
+        }
+        Ok(())
+    }
+
+    /// Reads enough bytes to fill `buffer`, with the data line released
+    ///
+    /// The data line is switched to an input for the reads and driven again
+    /// afterwards.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let gpio = unsafe { &*GPIO::ptr() };
+        let pin = self.sdio.pin_id();
+        let pincnf = &gpio.pin_cnf[pin as usize];
+
+        // Release the line before disconnecting it from MOSI, so it is never
+        // driven from the GPIO output register in between
+        pincnf.modify(|_, w| w.dir().input().input().connect());
+        self.spi
+            .pselmosi
+            .write(|w| unsafe { w.bits(PSEL_DISCONNECTED) });
+        self.spi.pselmiso.write(|w| unsafe { w.bits(pin.into()) });
+
+        for byte in buffer.iter_mut() {
+            *byte = self.transfer_byte(0);
+        }
+
+        self.spi
+            .pselmiso
+            .write(|w| unsafe { w.bits(PSEL_DISCONNECTED) });
+        self.spi.pselmosi.write(|w| unsafe { w.bits(pin.into()) });
+        pincnf.modify(|_, w| w.dir().output().input().disconnect());
+        Ok(())
+    }
+
+    /// Disables the SPI and returns the pins
+    pub fn teardown(self) -> (SCK, SDIO) {
+        self.spi.enable.write(|w| w.enable().disabled());
+        (self.sck, self.sdio)
+    }
+
+    fn transfer_byte(&mut self, byte: u8) -> u8 {
+        self.spi.txd.write(|w| unsafe { w.bits(u32::from(byte)) });
+        while self.spi.events_ready.read().bits() == 0 {}
+        self.spi.events_ready.reset();
+        self.spi.rxd.read().bits() as u8
+    }
+}
+
 /// Default implementation
-impl<X> write::Default<u8> for Spi<X>
+impl<X, SCK, MOSI, MISO> write::Default<u8> for Spi<X, SCK, MOSI, MISO>
 where
-    Spi<X>: FullDuplex<u8>,
+    Spi<X, SCK, MOSI, MISO>: FullDuplex<u8>,
     X: SpiExt,
 {
 }
 /// Default implementation
-impl<X> write_iter::Default<u8> for Spi<X>
+impl<X, SCK, MOSI, MISO> write_iter::Default<u8> for Spi<X, SCK, MOSI, MISO>
 where
-    Spi<X>: FullDuplex<u8>,
+    Spi<X, SCK, MOSI, MISO>: FullDuplex<u8>,
     X: SpiExt,
 {
 }
 /// Default implementaion
-impl<X> transfer::Default<u8> for Spi<X>
+impl<X, SCK, MOSI, MISO> transfer::Default<u8> for Spi<X, SCK, MOSI, MISO>
 where
-    Spi<X>: FullDuplex<u8>,
+    Spi<X, SCK, MOSI, MISO>: FullDuplex<u8>,
     X: SpiExt,
 {
 }
 
-impl<SPI> FullDuplex<u8> for Spi<SPI>
+impl<SPI, SCK, MOSI, MISO> FullDuplex<u8> for Spi<SPI, SCK, MOSI, MISO>
 where
     SPI: SpiExt,
 {
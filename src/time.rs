@@ -1,11 +1,39 @@
 //! Time conversions for the high and low frequency clocks.
 
+use core::ops::{Add, AddAssign, Sub, SubAssign};
 use core::time::Duration;
 
 use cast::u32;
 
-use crate::hi_res_timer::HFCLK_MHZ;
-use crate::lo_res_timer::LFCLK_HZ;
+use crate::hi_res_timer::{TimerFrequency, HFCLK_MHZ};
+use crate::lo_res_timer::{RtcFrequency, LFCLK_HZ};
+
+/// Operations common to `Hfticks` and `Lfticks`.
+///
+/// This lets code be written once over both clocks. `Hfticks` and `Lfticks`
+/// also provide `from_ms()`, `from_us()` and `to_duration()` as inherent
+/// methods, so the trait only needs importing for generic code.
+pub trait ClockTicks: Copy + From<Duration> {
+    /// The frequency setting of the peripherals counting this clock:
+    /// `TimerFrequency` for the TIMERs, `RtcFrequency` for the RTCs.
+    type Frequency: Copy;
+
+    /// Converts a time in milliseconds to a number of ticks.
+    fn from_ms(ms: u32) -> Self;
+
+    /// Converts a time in microseconds to a number of ticks.
+    fn from_us(us: u32) -> Self;
+
+    /// Converts the tick count to the equivalent number of ticks of a
+    /// peripheral running at `frequency`.
+    ///
+    /// Rounds down. Returns `None` if the result doesn't fit in a u32.
+    fn scale(self, frequency: Self::Frequency) -> Option<u32>;
+
+    /// Converts the tick count to a `Duration`, rounded down to the
+    /// nanosecond.
+    fn to_duration(self) -> Duration;
+}
 
 /// A number of ticks of the nRF51 high-frequency clock (HFCLK).
 ///
@@ -14,7 +42,7 @@ use crate::lo_res_timer::LFCLK_HZ;
 /// All TIMER frequencies are a multiple of this base frequency.
 ///
 /// Holds a 64-bit number.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Hfticks(pub u64);
 
 impl Hfticks {
@@ -29,6 +57,36 @@ impl Hfticks {
     pub fn from_us(us: u32) -> Hfticks {
         Hfticks(us as u64 * HFCLK_MHZ as u64)
     }
+
+    /// Converts a number of ticks of the high-frequency clock to a
+    /// `Duration`.
+    ///
+    /// Rounds down to the nanosecond.
+    pub fn to_duration(self) -> Duration {
+        let hz = HFCLK_MHZ as u64 * 1_000_000;
+        let nanos = (self.0 % hz) * 1000 / HFCLK_MHZ as u64;
+        Duration::new(self.0 / hz, nanos as u32)
+    }
+}
+
+impl ClockTicks for Hfticks {
+    type Frequency = TimerFrequency;
+
+    fn from_ms(ms: u32) -> Hfticks {
+        Hfticks::from_ms(ms)
+    }
+
+    fn from_us(us: u32) -> Hfticks {
+        Hfticks::from_us(us)
+    }
+
+    fn scale(self, frequency: TimerFrequency) -> Option<u32> {
+        frequency.scale(self.0)
+    }
+
+    fn to_duration(self) -> Duration {
+        Hfticks::to_duration(self)
+    }
 }
 
 /// Converts a core::time::Duration to a number of ticks of the high-frequency
@@ -57,7 +115,7 @@ impl From<Duration> for Hfticks {
 /// All RTC frequencies are a multiple of this base frequency.
 ///
 /// Holds a 64-bit number.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Lfticks(pub u64);
 
 /// Converts a core::time::Duration to a number of ticks of the low-frequency
@@ -94,4 +152,155 @@ impl Lfticks {
     pub fn from_us(us: u32) -> Lfticks {
         Lfticks((us as u64 * LFCLK_HZ as u64) / 1_000_000)
     }
+
+    /// Converts a time in milliseconds to a number of ticks of the
+    /// low-frequency clock.
+    ///
+    /// Rounds to the nearest tick, so the error is at most half a tick
+    /// rather than up to a whole tick as with `from_ms()`. For example 250ms
+    /// is 8192 ticks either way, but 1ms is 33 ticks here (32.768 exactly)
+    /// against 32 with `from_ms()`.
+    pub fn from_ms_round(ms: u32) -> Lfticks {
+        Lfticks((ms as u64 * LFCLK_HZ as u64 + 500) / 1_000)
+    }
+
+    /// Converts a time in microseconds to a number of ticks of the
+    /// low-frequency clock.
+    ///
+    /// Rounds to the nearest tick.
+    pub fn from_us_round(us: u32) -> Lfticks {
+        Lfticks((us as u64 * LFCLK_HZ as u64 + 500_000) / 1_000_000)
+    }
+
+    /// Converts a number of ticks of the low-frequency clock to a
+    /// `Duration`.
+    ///
+    /// Rounds down to the nanosecond.
+    pub fn to_duration(self) -> Duration {
+        let secs = self.0 / LFCLK_HZ as u64;
+        let nanos = (self.0 % LFCLK_HZ as u64) * 1_000_000_000 / LFCLK_HZ as u64;
+        Duration::new(secs, nanos as u32)
+    }
+}
+
+impl ClockTicks for Lfticks {
+    type Frequency = RtcFrequency;
+
+    fn from_ms(ms: u32) -> Lfticks {
+        Lfticks::from_ms(ms)
+    }
+
+    fn from_us(us: u32) -> Lfticks {
+        Lfticks::from_us(us)
+    }
+
+    fn scale(self, frequency: RtcFrequency) -> Option<u32> {
+        frequency.scale(self.0)
+    }
+
+    fn to_duration(self) -> Duration {
+        Lfticks::to_duration(self)
+    }
+}
+
+macro_rules! impl_tick_ops {
+    ($($ticks:ident,)*) => {
+        $(
+            impl $ticks {
+                /// Adds two tick counts, returning `None` on overflow.
+                pub fn checked_add(self, rhs: $ticks) -> Option<$ticks> {
+                    self.0.checked_add(rhs.0).map($ticks)
+                }
+
+                /// Subtracts two tick counts, returning `None` if `rhs` is
+                /// larger.
+                pub fn checked_sub(self, rhs: $ticks) -> Option<$ticks> {
+                    self.0.checked_sub(rhs.0).map($ticks)
+                }
+
+                /// Adds two tick counts, saturating at the largest count.
+                pub fn saturating_add(self, rhs: $ticks) -> $ticks {
+                    $ticks(self.0.saturating_add(rhs.0))
+                }
+
+                /// Subtracts two tick counts, saturating at zero.
+                pub fn saturating_sub(self, rhs: $ticks) -> $ticks {
+                    $ticks(self.0.saturating_sub(rhs.0))
+                }
+            }
+
+            /// # Panics
+            ///
+            /// Panics on overflow; see `checked_add()` and `saturating_add()`.
+            impl Add for $ticks {
+                type Output = $ticks;
+
+                fn add(self, rhs: $ticks) -> $ticks {
+                    self.checked_add(rhs).expect("tick count overflow")
+                }
+            }
+
+            /// # Panics
+            ///
+            /// Panics if `rhs` is larger; see `checked_sub()` and
+            /// `saturating_sub()`.
+            impl Sub for $ticks {
+                type Output = $ticks;
+
+                fn sub(self, rhs: $ticks) -> $ticks {
+                    self.checked_sub(rhs).expect("tick count underflow")
+                }
+            }
+
+            impl AddAssign for $ticks {
+                fn add_assign(&mut self, rhs: $ticks) {
+                    *self = *self + rhs;
+                }
+            }
+
+            impl SubAssign for $ticks {
+                fn sub_assign(&mut self, rhs: $ticks) {
+                    *self = *self - rhs;
+                }
+            }
+        )*
+    }
+}
+
+impl_tick_ops!(Hfticks, Lfticks,);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lfticks_rounding_differs_from_truncation() {
+        // 1ms is 32.768 ticks
+        assert_eq!(Lfticks::from_ms(1), Lfticks(32));
+        assert_eq!(Lfticks::from_ms_round(1), Lfticks(33));
+        // 50µs is 1.6384 ticks
+        assert_eq!(Lfticks::from_us(50), Lfticks(1));
+        assert_eq!(Lfticks::from_us_round(50), Lfticks(2));
+        // 40µs is 1.31072 ticks, below the half-tick mark
+        assert_eq!(Lfticks::from_us(40), Lfticks(1));
+        assert_eq!(Lfticks::from_us_round(40), Lfticks(1));
+    }
+
+    #[test]
+    fn lfticks_exact_values_agree() {
+        for ms in [0, 125, 250, 1000, 60_000].iter() {
+            assert_eq!(Lfticks::from_ms(*ms), Lfticks::from_ms_round(*ms));
+        }
+        assert_eq!(Lfticks::from_ms_round(250), Lfticks(8192));
+        assert_eq!(Lfticks::from_us_round(1_000_000), Lfticks(32768));
+    }
+
+    #[test]
+    fn lfticks_rounding_error_is_at_most_half_a_tick() {
+        for us in 0..10_000_u32 {
+            let exact = u64::from(us) * LFCLK_HZ as u64;
+            let rounded = Lfticks::from_us_round(us).0 * 1_000_000;
+            assert!(rounded.abs_diff(exact) <= 500_000, "{}µs", us);
+        }
+    }
 }
@@ -10,7 +10,7 @@ use nrf51::TIMER0;
 
 use crate::hi_res_timer::{HiResTimer, Nrf51Timer, TimerCc, TimerFrequency, TimerWidth};
 use crate::lo_res_timer::{LoResTimer, Nrf51Rtc, RtcCc, RtcFrequency};
-use crate::time::{Hfticks, Lfticks};
+use crate::time::{ClockTicks, Hfticks, Lfticks};
 
 /// A TIMER peripheral as a `CountDown` provider.
 ///
@@ -67,6 +67,15 @@ impl<T: Nrf51Timer> CountDownTimer<T> {
     pub fn free(self) -> T {
         self.timer.free()
     }
+
+    /// Gives access to the underlying `HiResTimer`.
+    ///
+    /// This allows using the CC registers other than CC0 while the count-down
+    /// is running, e.g. through a `SharedDelayTimer`. CC0 and the counter
+    /// itself must be left alone.
+    pub fn hi_res_timer(&mut self) -> &mut HiResTimer<T, T::MaxWidth> {
+        &mut self.timer
+    }
 }
 
 impl<T: Nrf51Timer> CountDown for CountDownTimer<T> {
@@ -77,10 +86,8 @@ impl<T: Nrf51Timer> CountDown for CountDownTimer<T> {
         D: Into<Self::Time>,
     {
         let hfticks = count.into();
-        let ticks = self
-            .timer
-            .frequency()
-            .scale(hfticks.0)
+        let ticks = hfticks
+            .scale(self.timer.frequency())
             .expect("TIMER compare value overflow");
         let ticks = T::MaxWidth::try_from_u32(ticks).expect("TIMER compare value too wide");
         // Stop the timer to make sure the event doesn't occur while we're
@@ -103,6 +110,237 @@ impl<T: Nrf51Timer> CountDown for CountDownTimer<T> {
 
 impl<T: Nrf51Timer> Periodic for CountDownTimer<T> {}
 
+/// A TIMER peripheral managing up to four independent count-downs.
+///
+/// Each of the four CC registers can be armed with its own interval, and
+/// `wait_any()` reports which one has expired. This gives several software
+/// timers for the price of one TIMER.
+///
+/// All channels share the same free-running counter, and so the same
+/// frequency and maximum interval (set by the TIMER's bit-width; see
+/// `TimerFrequency` for a table). The counter is never cleared, so arming one
+/// channel doesn't affect the others.
+///
+/// The count-downs are one-shot: a channel is disarmed when `wait_any()`
+/// reports it. A zero-length count-down has expired as soon as it is armed.
+///
+/// Besides the COMPARE event, `wait_any()` checks each armed count-down
+/// against the counter, which it reads by capturing it into the count-down's
+/// CC register (and then restoring the target). This catches a target the
+/// counter passed while the count-down was being armed, which the COMPARE
+/// event would only report after a whole counter wrap. For the check to work
+/// `wait_any()` must be called at least once per counter range.
+///
+/// # Panics
+///
+/// `arm()` panics if the requested time requires as many ticks at the set
+/// frequency as the timer's counter range, or more.
+///
+/// # Example
+/// ```ignore
+/// use core::time::Duration;
+/// use nb::block;
+/// use nrf51_hal::hi_res_timer::{TimerCc, TimerFrequency};
+/// use nrf51_hal::timer::MultiCountDown;
+/// let p = nrf51::Peripherals::take().unwrap();
+/// let mut timers = MultiCountDown::new(p.TIMER0, TimerFrequency::Freq1MHz);
+/// timers.arm(TimerCc::CC0, Duration::from_millis(10));
+/// timers.arm(TimerCc::CC1, Duration::from_millis(25));
+/// let first = block!(timers.wait_any()).unwrap(); // TimerCc::CC0
+/// ```
+pub struct MultiCountDown<T: Nrf51Timer> {
+    timer: HiResTimer<T, T::MaxWidth>,
+    armed: [Option<Armed>; 4],
+}
+
+/// A running count-down: the counter value when it was armed, and its length
+#[derive(Clone, Copy)]
+struct Armed {
+    start: u32,
+    ticks: u32,
+}
+
+impl<T: Nrf51Timer> MultiCountDown<T> {
+    /// Returns a new `MultiCountDown` wrapping the passed TIMER.
+    ///
+    /// Takes ownership of the TIMER peripheral.
+    ///
+    /// The TIMER is set to the greatest bit-width it supports and the
+    /// specified frequency, and started.
+    pub fn new(timer: T, frequency: TimerFrequency) -> MultiCountDown<T> {
+        let mut hi_res_timer = timer.as_max_width_timer();
+        hi_res_timer.set_frequency(frequency);
+        hi_res_timer.start();
+        MultiCountDown {
+            timer: hi_res_timer,
+            armed: [None; 4],
+        }
+    }
+
+    /// Gives the underlying `nrf51::TIMER`*n* instance back.
+    pub fn free(self) -> T {
+        self.timer.free()
+    }
+
+    /// Starts a count-down on the specified CC register.
+    ///
+    /// If the register was already armed it is restarted with the new
+    /// interval.
+    pub fn arm<D>(&mut self, register: TimerCc, count: D)
+    where
+        D: Into<Hfticks>,
+    {
+        let hfticks = count.into();
+        let ticks = hfticks
+            .scale(self.timer.frequency())
+            .expect("TIMER compare value overflow");
+        assert!(
+            ticks <= T::MaxWidth::max_value(),
+            "TIMER compare value too wide"
+        );
+
+        self.timer.capture(register);
+        let start = self.timer.captured_counter(register);
+        let armed = Armed { start, ticks };
+        self.timer.clear_compare_event(register);
+        self.set_target(register, armed);
+        self.armed[register as usize] = Some(armed);
+    }
+
+    /// Cancels the count-down on the specified CC register.
+    pub fn disarm(&mut self, register: TimerCc) {
+        self.armed[register as usize] = None;
+        self.timer.clear_compare_event(register);
+    }
+
+    /// Returns true if the specified CC register has a count-down running.
+    pub fn is_armed(&self, register: TimerCc) -> bool {
+        self.armed[register as usize].is_some()
+    }
+
+    fn set_target(&mut self, register: TimerCc, armed: Armed) {
+        // The counter range is a power of two, so masking wraps the target
+        let target = armed.start.wrapping_add(armed.ticks) & T::MaxWidth::max_value();
+        let target = T::MaxWidth::try_from_u32(target).expect("TIMER compare value too wide");
+        self.timer.set_compare_register(register, target);
+    }
+
+    /// Returns true if the counter has passed the count-down's target,
+    /// whether or not the COMPARE event was generated.
+    fn has_passed(&mut self, register: TimerCc, armed: Armed) -> bool {
+        if armed.ticks == 0 {
+            return true;
+        }
+        self.timer.capture(register);
+        let now = self.timer.captured_counter(register);
+        self.set_target(register, armed);
+        (now.wrapping_sub(armed.start) & T::MaxWidth::max_value()) >= armed.ticks
+    }
+
+    /// Checks for an expired count-down.
+    ///
+    /// Returns the CC register of an armed count-down that has expired,
+    /// disarming it. If several have expired, the lowest-numbered register is
+    /// returned first. Returns `WouldBlock` if none has.
+    pub fn wait_any(&mut self) -> Result<TimerCc, Void> {
+        for (index, register) in [TimerCc::CC0, TimerCc::CC1, TimerCc::CC2, TimerCc::CC3]
+            .iter()
+            .enumerate()
+        {
+            if let Some(armed) = self.armed[index] {
+                if self.timer.poll_compare_event(*register) || self.has_passed(*register, armed) {
+                    self.armed[index] = None;
+                    return Ok(*register);
+                }
+            }
+        }
+        Err(Error::WouldBlock)
+    }
+}
+
+/// A TIMER peripheral as a stopwatch, for measuring elapsed time.
+///
+/// `start()` resets the counter and lets it run; `elapsed()` reads how much
+/// time has passed since then, without stopping it.
+///
+/// The counter stops at its maximum value rather than wrapping around, so an
+/// interval which is too long to be measured is reported as such instead of
+/// as a short one. The longest measurable interval is the timer's overflow
+/// period, which depends on the bit-width (32-bit for TIMER0, 16-bit
+/// otherwise) and frequency; see `TimerFrequency` for a table. For example
+/// at 1MHz TIMER0 measures up to 71 minutes with 1µs resolution, but TIMER1
+/// and TIMER2 only up to 65ms.
+///
+/// # Example
+/// ```ignore
+/// use nrf51_hal::hi_res_timer::TimerFrequency;
+/// use nrf51_hal::timer::Stopwatch;
+/// let p = nrf51::Peripherals::take().unwrap();
+/// let mut stopwatch = Stopwatch::new(p.TIMER0, TimerFrequency::Freq1MHz);
+/// stopwatch.start();
+/// do_something();
+/// let took = stopwatch.elapsed().unwrap();
+/// ```
+pub struct Stopwatch<T: Nrf51Timer> {
+    timer: HiResTimer<T, T::MaxWidth>,
+}
+
+impl<T: Nrf51Timer> Stopwatch<T> {
+    /// Returns a new `Stopwatch` wrapping the passed TIMER.
+    ///
+    /// Takes ownership of the TIMER peripheral.
+    ///
+    /// The TIMER is set to the greatest bit-width it supports, and the
+    /// specified frequency. It doesn't run until `start()` is called.
+    pub fn new(timer: T, frequency: TimerFrequency) -> Stopwatch<T> {
+        let mut hi_res_timer = timer.as_max_width_timer();
+        hi_res_timer.set_frequency(frequency);
+        // CC0 stops the counter at its maximum value, marking an overflow
+        let max = T::MaxWidth::try_from_u32(T::MaxWidth::max_value()).unwrap();
+        hi_res_timer.set_compare_register(TimerCc::CC0, max);
+        hi_res_timer.enable_auto_stop(TimerCc::CC0);
+        Stopwatch {
+            timer: hi_res_timer,
+        }
+    }
+
+    /// Stops the TIMER and gives the underlying `nrf51::TIMER`*n* instance
+    /// back.
+    pub fn free(self) -> T {
+        self.timer.free()
+    }
+
+    /// Starts measuring, from zero.
+    pub fn start(&mut self) {
+        self.timer.stop();
+        self.timer.clear();
+        self.timer.clear_compare_event(TimerCc::CC0);
+        self.timer.start();
+    }
+
+    /// Stops the stopwatch.
+    ///
+    /// `elapsed()` keeps returning the time measured up to this point.
+    pub fn stop(&mut self) {
+        self.timer.stop();
+    }
+
+    /// Returns the time elapsed since `start()`.
+    ///
+    /// Rounds down to the nanosecond. Returns `None` if the elapsed time is
+    /// too long to be measured at the TIMER's bit-width and frequency.
+    pub fn elapsed(&mut self) -> Option<Duration> {
+        if self.timer.read_compare_event(TimerCc::CC0) {
+            return None;
+        }
+        self.timer.capture(TimerCc::CC1);
+        let ticks = u64::from(self.timer.captured_counter(TimerCc::CC1));
+        // Each tick is 2^prescaler cycles of the 16MHz base clock
+        let prescaler = self.timer.frequency().as_prescaler();
+        Some(Hfticks(ticks << prescaler).to_duration())
+    }
+}
+
 /// An RTC peripheral as a `CountDown` provider.
 ///
 /// `CountDownRTC` instances implement the embedded-hal `CountDown` trait.
@@ -173,10 +411,8 @@ impl<T: Nrf51Rtc> CountDown for CountDownRtc<T> {
         D: Into<Self::Time>,
     {
         let lfticks = count.into();
-        let ticks = self
-            .timer
-            .frequency()
-            .scale(lfticks.0)
+        let ticks = lfticks
+            .scale(self.timer.frequency())
             .expect("RTC compare value overflow");
         // Stop the timer to make sure the event doesn't occur while we're
         // setting things up.